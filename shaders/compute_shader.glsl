@@ -1,11 +1,25 @@
 #version 460 core
 
 layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
-layout(rgba32f, binding = 0) uniform image2D screen;
+layout(rgba32f, binding = 0) uniform image2D screen; // rgb color, a accumulated sample count
+layout(rgba32f, binding = 2) uniform image2D variance_image; // x mean luminance, y sum of squared deviations, z relative error, w sample count
 
-const int max_objects = 10;
-const int num_motion_blur_samples = 5; // Reduce number of samples for better performance
-const int bounces = 5;
+// Render settings, see RenderSettings in src/window_manager/window_manager.rs
+uniform int max_bounces;
+uniform int samples_per_pixel; // Samples per pixel per dispatch
+uniform int rr_min_depth; // Bounces before russian roulette may end a path
+uniform bool is_low_discrepancy; // Owen scrambled Sobol camera samples instead of plain PCG
+uniform bool is_adaptive; // Skip converged pixels and spend more samples on noisy ones
+uniform float adaptive_threshold; // Relative error below which a pixel counts as converged
+uniform int adaptive_min_samples; // Samples before a pixel may be judged converged
+uniform int adaptive_max_factor; // Most samples_per_pixel multiples a noisy pixel gets per dispatch
+uniform ivec2 tile_offset; // Pixel offset of the current tile when rendering in tiles
+uniform ivec2 tile_end; // First pixel past the current tile, invocations beyond it belong to the next one
+uniform float shutter_open; // Shutter interval in seconds relative to the current frame
+uniform float shutter_close;
+
+// Time of the path being traced, objects are moved to this time before intersection
+float ray_time = 0.0;
 struct Ray {
     vec3 Origin;
     vec3 Direction;
@@ -20,25 +34,86 @@ uniform vec3 camera_right;
 uniform vec3 camera_velocity;
 uniform float fov; // Field of View in radians
 uniform bool is_fisheye;
+uniform bool is_spectral; // Each path carries a wavelength, glass IOR depends on it
+uniform float fog_density; // Extinction of the global fog, 0 disables it
+uniform vec3 fog_albedo;
+uniform float fog_anisotropy;
+uniform float fog_extent; // Fog distance travelled by rays that escape to the sky
 float focal_length = 5.0; // Focal length for depth of field
 float aperture = 0.01; // Aperture size for depth of field
 
-// Objects
-uniform vec3 objects_size[max_objects]; // Use this for cube dimensions
-uniform vec3 objects_color[max_objects];
-uniform vec3 objects_position[max_objects];
-uniform float objects_radius[max_objects]; // Use this for sphere radius
-uniform float objects_roughness[max_objects];
-uniform float objects_emission[max_objects];
-uniform bool is_cube[max_objects];
-uniform bool is_glass[max_objects];
-uniform float objects_refractive_index[max_objects];
-
-const int max_triangles = 20; // Maximum number of triangles in the scene
-// Meshes
-uniform vec3 vertices[max_triangles * 3];
-uniform vec3 normals[max_triangles * 3];
-uniform ivec3 triangles[max_triangles];
+// Objects, see GpuObject in src/gpu_scene.rs for the matching Rust layout
+struct SceneObject {
+    vec3 position;
+    float radius; // Use this for sphere radius
+    vec3 size; // Use this for cube dimensions
+    float roughness;
+    vec3 color;
+    float emission;
+    vec3 medium; // Scattering, absorption, anisotropy of volume interiors
+    float ior;
+    vec3 subsurface_radius; // Mean free path per channel
+    float subsurface; // Subsurface weight
+    ivec3 textures; // Texture graph for color, roughness, emission (-1 for none)
+    float dispersion; // Cauchy B coefficient in um^2, only used in spectral mode
+    int is_cube;
+    int is_glass;
+    int is_volume;
+    int padding;
+    vec3 velocity; // Units per second, used for motion blur
+    float padding1;
+    vec3 angular_velocity; // Radians per second in world space
+    float padding2;
+    vec4 orientation; // Quaternion (x, y, z, w) at shutter time 0
+};
+
+layout(std430, binding = 1) buffer ObjectBuffer {
+    SceneObject objects[];
+};
+uniform int num_objects;
+
+// Procedural textures, see src/procedural.rs for the node layout
+const int max_texture_graphs = 8;
+const int max_texture_nodes = 32;
+uniform int num_texture_graphs;
+uniform ivec2 texture_graph_range[max_texture_graphs]; // First node, node count
+uniform int texture_node_kind[max_texture_nodes];
+uniform vec4 texture_node_params[max_texture_nodes]; // Scale, param, input a, input b
+uniform vec3 texture_node_color_a[max_texture_nodes];
+uniform vec3 texture_node_color_b[max_texture_nodes];
+
+// Mesh instances, see src/gpu_scene.rs. Each mesh has a BLAS over its
+// triangles in object space, the TLAS is built over the instances each frame.
+struct BvhNode {
+    vec3 aabb_min;
+    int left_first; // First primitive for leaves, left child for interior nodes
+    vec3 aabb_max;
+    int count; // Primitives in a leaf, 0 for interior nodes
+};
+
+struct MeshTriangle {
+    vec4 v0, v1, v2; // Object space positions in xyz
+    vec4 n0, n1, n2;
+};
+
+struct MeshInstance {
+    mat4 world_to_local;
+    vec3 color;
+    float roughness;
+    int node_offset; // First BLAS node of the mesh
+    int triangle_offset; // First triangle of the mesh
+    float emission;
+    int padding;
+};
+
+layout(std430, binding = 3) readonly buffer TriangleBuffer { MeshTriangle mesh_triangles[]; };
+layout(std430, binding = 4) readonly buffer BlasBuffer { BvhNode blas_nodes[]; };
+layout(std430, binding = 5) readonly buffer InstanceBuffer { MeshInstance instances[]; };
+layout(std430, binding = 6) readonly buffer TlasBuffer { BvhNode tlas_nodes[]; };
+uniform int num_instances;
+
+const int BVH_STACK_SIZE = 32;
+const float NO_HIT = 1e30;
 // Instead of fetching from uniforms, hardcode these values
 vec3 albedo = vec3(0.5); // Gray color
 float roughness = 0.5;  // Roughness set to 0.5
@@ -77,6 +152,73 @@ float random(inout uint rngState, float min, float max)
     return min + (max - min) * random(rngState);
 }
 
+// Owen scrambled Sobol sequence, mirrors src/sampler.rs.
+// Direction numbers for dimensions 1..3, dimension 0 is the bit reversed index.
+const uint sobol_directions[96] = uint[](
+    0x80000000u, 0xc0000000u, 0xa0000000u, 0xf0000000u, 0x88000000u, 0xcc000000u, 0xaa000000u, 0xff000000u,
+    0x80800000u, 0xc0c00000u, 0xa0a00000u, 0xf0f00000u, 0x88880000u, 0xcccc0000u, 0xaaaa0000u, 0xffff0000u,
+    0x80008000u, 0xc000c000u, 0xa000a000u, 0xf000f000u, 0x88008800u, 0xcc00cc00u, 0xaa00aa00u, 0xff00ff00u,
+    0x80808080u, 0xc0c0c0c0u, 0xa0a0a0a0u, 0xf0f0f0f0u, 0x88888888u, 0xccccccccu, 0xaaaaaaaau, 0xffffffffu,
+    0x80000000u, 0xc0000000u, 0x60000000u, 0x90000000u, 0xe8000000u, 0x5c000000u, 0x8e000000u, 0xc5000000u,
+    0x68800000u, 0x9cc00000u, 0xee600000u, 0x55900000u, 0x80680000u, 0xc09c0000u, 0x60ee0000u, 0x90550000u,
+    0xe8808000u, 0x5cc0c000u, 0x8e606000u, 0xc5909000u, 0x6868e800u, 0x9c9c5c00u, 0xeeee8e00u, 0x5555c500u,
+    0x8000e880u, 0xc0005cc0u, 0x60008e60u, 0x9000c590u, 0xe8006868u, 0x5c009c9cu, 0x8e00eeeeu, 0xc5005555u,
+    0x80000000u, 0xc0000000u, 0x20000000u, 0x50000000u, 0xf8000000u, 0x74000000u, 0xa2000000u, 0x93000000u,
+    0xd8800000u, 0x25400000u, 0x59e00000u, 0xe6d00000u, 0x78080000u, 0xb40c0000u, 0x82020000u, 0xc3050000u,
+    0x208f8000u, 0x51474000u, 0xfbea2000u, 0x75d93000u, 0xa0858800u, 0x914e5400u, 0xdbe79e00u, 0x25db6d00u,
+    0x58800080u, 0xe54000c0u, 0x79e00020u, 0xb6d00050u, 0x800800f8u, 0xc00c0074u, 0x200200a2u, 0x50050093u
+);
+
+uint pcgHash(uint x)
+{
+    uint state = x * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+uint hashCombine(uint seed, uint value)
+{
+    return seed ^ (value + 0x9e3779b9u + (seed << 6u) + (seed >> 2u));
+}
+
+uint sobol(uint index, int dim)
+{
+    if (dim == 0)
+        return bitfieldReverse(index);
+    uint x = 0u;
+    for (int bit = 0; bit < 32; ++bit)
+    {
+        if (((index >> uint(bit)) & 1u) != 0u)
+            x ^= sobol_directions[(dim - 1) * 32 + bit];
+    }
+    return x;
+}
+
+uint nestedUniformScramble(uint x, uint seed)
+{
+    // Laine-Karras permutation on the reversed bits
+    x = bitfieldReverse(x);
+    x += seed;
+    x ^= x * 0x6c50b47cu;
+    x ^= x * 0xb82f1e52u;
+    x ^= x * 0xc7afe638u;
+    x ^= x * 0x8d22f6e6u;
+    return bitfieldReverse(x);
+}
+
+// First four dimensions of sample `index` for the pixel with `seed`, in [0,1)
+vec4 sobolSample(uint index, uint seed)
+{
+    index = nestedUniformScramble(index, seed);
+    vec4 values;
+    for (int dim = 0; dim < 4; ++dim)
+    {
+        uint x = nestedUniformScramble(sobol(index, dim), hashCombine(seed, uint(dim)));
+        values[dim] = float(x >> 8u) / 16777216.0;
+    }
+    return values;
+}
+
 vec3 random_in_unit_sphere(inout uint rngState)
 {
     vec3 p = vec3(random(rngState, -1.0, 1.0), random(rngState, -1.0, 1.0), random(rngState, -1.0, 1.0));
@@ -134,147 +276,906 @@ vec3 reflect(vec3 v, vec3 n)
 }
 
 
+// Must stay in sync with hash3 in src/procedural.rs
+uint hash3(ivec3 c)
+{
+    uint h = uint(c.x) * 0x8da6b343u ^ uint(c.y) * 0xd8163841u ^ uint(c.z) * 0xcb1ab31fu;
+    h ^= h >> 16;
+    h *= 0x7feb352du;
+    h ^= h >> 15;
+    h *= 0x846ca68bu;
+    h ^= h >> 16;
+    return h;
+}
+
+float grad(uint hash, vec3 p)
+{
+    uint h = hash & 15u;
+    float u = h < 8u ? p.x : p.y;
+    float v = h < 4u ? p.y : ((h == 12u || h == 14u) ? p.x : p.z);
+    return ((h & 1u) == 0u ? u : -u) + ((h & 2u) == 0u ? v : -v);
+}
+
+float perlin(vec3 p)
+{
+    vec3 cell = floor(p);
+    ivec3 i = ivec3(cell);
+    vec3 f = p - cell;
+    vec3 w = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+    float n000 = grad(hash3(i), f);
+    float n100 = grad(hash3(i + ivec3(1, 0, 0)), f - vec3(1, 0, 0));
+    float n010 = grad(hash3(i + ivec3(0, 1, 0)), f - vec3(0, 1, 0));
+    float n110 = grad(hash3(i + ivec3(1, 1, 0)), f - vec3(1, 1, 0));
+    float n001 = grad(hash3(i + ivec3(0, 0, 1)), f - vec3(0, 0, 1));
+    float n101 = grad(hash3(i + ivec3(1, 0, 1)), f - vec3(1, 0, 1));
+    float n011 = grad(hash3(i + ivec3(0, 1, 1)), f - vec3(0, 1, 1));
+    float n111 = grad(hash3(i + ivec3(1, 1, 1)), f - vec3(1, 1, 1));
+
+    return mix(mix(mix(n000, n100, w.x), mix(n010, n110, w.x), w.y),
+               mix(mix(n001, n101, w.x), mix(n011, n111, w.x), w.y), w.z);
+}
+
+float fbm(vec3 p, float octaves)
+{
+    int count = clamp(int(octaves), 1, 8);
+    float sum = 0.0;
+    float amplitude = 1.0;
+    float total = 0.0;
+    float frequency = 1.0;
+    for (int i = 0; i < count; ++i)
+    {
+        sum += amplitude * perlin(p * frequency);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return sum / total;
+}
+
+float voronoi(vec3 p, float jitter)
+{
+    ivec3 cell = ivec3(floor(p));
+    float closest = 8.0;
+    for (int dz = -1; dz <= 1; ++dz)
+    for (int dy = -1; dy <= 1; ++dy)
+    for (int dx = -1; dx <= 1; ++dx)
+    {
+        ivec3 c = cell + ivec3(dx, dy, dz);
+        uint h = hash3(c);
+        vec3 offset = vec3(float(h & 0x3ffu), float((h >> 10) & 0x3ffu), float((h >> 20) & 0x3ffu)) / 1023.0;
+        vec3 feature = vec3(c) + 0.5 + (offset - 0.5) * jitter;
+        closest = min(closest, length(feature - p));
+    }
+    return closest;
+}
+
+float pattern_factor(int kind, vec3 p, float param)
+{
+    if (kind == 0) // Checker
+    {
+        float sum = floor(p.x) + floor(p.y) + floor(p.z);
+        return mod(sum, 2.0) < 1.0 ? 0.0 : 1.0;
+    }
+    else if (kind == 1) // Grid
+    {
+        vec3 f = fract(p);
+        return (any(lessThan(f, vec3(param))) || any(greaterThan(f, vec3(1.0 - param)))) ? 1.0 : 0.0;
+    }
+    else if (kind == 2) // Noise
+    {
+        return clamp(fbm(p, param) * 0.5 + 0.5, 0.0, 1.0);
+    }
+    else if (kind == 3) // Voronoi
+    {
+        return clamp(voronoi(p, param), 0.0, 1.0);
+    }
+    else if (kind == 4) // Gradient
+    {
+        int axis = clamp(int(param), 0, 2);
+        return clamp(p[axis] * 0.5 + 0.5, 0.0, 1.0);
+    }
+    else // Brick
+    {
+        float row = floor(p.y);
+        float u = (p.x + p.z) * 0.5;
+        if (mod(row, 2.0) >= 1.0) u += 0.5;
+        float fu = fract(u);
+        float fv = p.y - row;
+        return (fu < param * 0.5 || fu > 1.0 - param * 0.5 || fv < param || fv > 1.0 - param) ? 1.0 : 0.0;
+    }
+}
+
+// Evaluates a texture graph at the object-space point p and returns its output color
+vec3 evaluate_texture(int graph, vec3 p)
+{
+    vec3 values[max_texture_nodes];
+    ivec2 range = texture_graph_range[graph];
+    for (int n = 0; n < range.y; ++n)
+    {
+        int node = range.x + n;
+        vec4 params = texture_node_params[node];
+        int input_a = int(params.z);
+        int input_b = int(params.w);
+        vec3 a = (input_a >= 0 && input_a < n) ? values[input_a] : texture_node_color_a[node];
+        vec3 b = (input_b >= 0 && input_b < n) ? values[input_b] : texture_node_color_b[node];
+        float t = pattern_factor(texture_node_kind[node], p * params.x, params.y);
+        values[n] = mix(a, b, t);
+    }
+    return range.y > 0 ? values[range.y - 1] : vec3(1.0);
+}
+
+float luminance(vec3 color)
+{
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
 void applyBloom(inout vec3 color, vec3 light, float threshold, float intensity)
 {
     vec3 bloomColor = max(vec3(0.0), light - threshold);
     bloomColor *= intensity;
     color += bloomColor;
 }
-vec3 calculateLightContribution(vec3 rayOrigin, vec3 rayDir, inout uint rngState, vec3 contribution)
+// Quaternions are stored as (x, y, z, w)
+vec4 quatMultiply(vec4 a, vec4 b)
 {
-    vec3 light = vec3(0.0);
+    return vec4(a.w * b.xyz + b.w * a.xyz + cross(a.xyz, b.xyz), a.w * b.w - dot(a.xyz, b.xyz));
+}
 
-    for (int bounce = 0; bounce < bounces; ++bounce) // Reduce number of bounces for better performance
+vec3 quatRotate(vec4 q, vec3 v)
+{
+    vec3 t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+vec3 quatRotateInverse(vec4 q, vec3 v)
+{
+    return quatRotate(vec4(-q.xyz, q.w), v);
+}
+
+// Object transform at ray_time, extrapolated from the velocities
+vec3 objectPosition(int i)
+{
+    return objects[i].position + objects[i].velocity * ray_time;
+}
+
+vec4 objectRotation(int i)
+{
+    vec4 rotation = normalize(objects[i].orientation);
+    vec3 angular_velocity = objects[i].angular_velocity;
+    float speed = length(angular_velocity);
+    if (speed * abs(ray_time) < 1e-6)
+        return rotation;
+    float half_angle = 0.5 * speed * ray_time;
+    vec4 delta = vec4(angular_velocity / speed * sin(half_angle), cos(half_angle));
+    // World space spin is applied on the left, like the physics integration
+    return normalize(quatMultiply(delta, rotation));
+}
+
+// Point in the object's local frame at ray_time
+vec3 objectLocalPoint(int i, vec3 p)
+{
+    return quatRotateInverse(objectRotation(i), p - objectPosition(i));
+}
+
+// Entry distance of the ray into the box, NO_HIT when missed or beyond t_max
+float intersectAabb(vec3 origin, vec3 inv_dir, vec3 aabb_min, vec3 aabb_max, float t_max)
+{
+    vec3 t0 = (aabb_min - origin) * inv_dir;
+    vec3 t1 = (aabb_max - origin) * inv_dir;
+    vec3 t_small = min(t0, t1);
+    vec3 t_large = max(t0, t1);
+    float t_near = max(max(t_small.x, t_small.y), t_small.z);
+    float t_far = min(min(t_large.x, t_large.y), t_large.z);
+    if (t_near > t_far || t_far <= 0.0 || t_near >= t_max)
+        return NO_HIT;
+    return max(t_near, 0.0);
+}
+
+// Moller-Trumbore, lowers t on a closer hit
+bool intersectTriangle(vec3 origin, vec3 dir, MeshTriangle tri, inout float t, out vec2 barycentric)
+{
+    barycentric = vec2(0.0);
+    vec3 e1 = tri.v1.xyz - tri.v0.xyz;
+    vec3 e2 = tri.v2.xyz - tri.v0.xyz;
+    vec3 p = cross(dir, e2);
+    float det = dot(e1, p);
+    if (abs(det) < 1e-10)
+        return false;
+    float inv_det = 1.0 / det;
+    vec3 to_origin = origin - tri.v0.xyz;
+    float u = dot(to_origin, p) * inv_det;
+    if (u < 0.0 || u > 1.0)
+        return false;
+    vec3 q = cross(to_origin, e1);
+    float v = dot(dir, q) * inv_det;
+    if (v < 0.0 || u + v > 1.0)
+        return false;
+    float t_hit = dot(e2, q) * inv_det;
+    if (t_hit <= 0.0001 || t_hit >= t)
+        return false;
+    t = t_hit;
+    barycentric = vec2(u, v);
+    return true;
+}
+
+// Closest triangle of an instance's mesh, the ray is in the mesh's object space
+bool traceBlas(int instance, vec3 origin, vec3 dir, inout float closest, out int triangle, out vec2 barycentric)
+{
+    int node_offset = instances[instance].node_offset;
+    int triangle_offset = instances[instance].triangle_offset;
+    vec3 inv_dir = 1.0 / dir;
+    bool hit = false;
+    triangle = -1;
+    barycentric = vec2(0.0);
+
+    int stack[BVH_STACK_SIZE];
+    int stack_size = 0;
+    stack[stack_size++] = 0;
+    while (stack_size > 0)
     {
-        // Track closest object information
-        float closestIntersection = 9999.0;
-        int closestObjectIndex = -1;
-        bool isSphere = true; // To distinguish between spheres and cubes
-        float objectRoughness = 0.0;
+        BvhNode node = blas_nodes[node_offset + stack[--stack_size]];
+        if (intersectAabb(origin, inv_dir, node.aabb_min, node.aabb_max, closest) == NO_HIT)
+            continue;
+        if (node.count > 0)
+        {
+            for (int k = 0; k < node.count; ++k)
+            {
+                int index = triangle_offset + node.left_first + k;
+                vec2 uv;
+                if (intersectTriangle(origin, dir, mesh_triangles[index], closest, uv))
+                {
+                    hit = true;
+                    triangle = index;
+                    barycentric = uv;
+                }
+            }
+        }
+        else if (stack_size + 2 <= BVH_STACK_SIZE)
+        {
+            // Visit the nearer child first
+            int near_child = node.left_first;
+            int far_child = node.left_first + 1;
+            BvhNode left = blas_nodes[node_offset + near_child];
+            BvhNode right = blas_nodes[node_offset + far_child];
+            if (intersectAabb(origin, inv_dir, left.aabb_min, left.aabb_max, closest) > intersectAabb(origin, inv_dir, right.aabb_min, right.aabb_max, closest))
+            {
+                near_child = node.left_first + 1;
+                far_child = node.left_first;
+            }
+            stack[stack_size++] = far_child;
+            stack[stack_size++] = near_child;
+        }
+    }
+    return hit;
+}
 
-        // Find closest object intersection
-        for (int i = 0; i < max_objects; ++i)
+// Closest mesh instance hit, closest is only lowered on a hit. The normal is
+// in world space and faces against the ray.
+bool traceInstances(vec3 origin, vec3 dir, inout float closest, out int instance, out vec3 normal)
+{
+    instance = -1;
+    normal = vec3(0.0, 1.0, 0.0);
+    if (num_instances == 0)
+        return false;
+
+    vec3 inv_dir = 1.0 / dir;
+    int hit_triangle = -1;
+    vec2 hit_barycentric = vec2(0.0);
+
+    int stack[BVH_STACK_SIZE];
+    int stack_size = 0;
+    stack[stack_size++] = 0;
+    while (stack_size > 0)
+    {
+        BvhNode node = tlas_nodes[stack[--stack_size]];
+        if (intersectAabb(origin, inv_dir, node.aabb_min, node.aabb_max, closest) == NO_HIT)
+            continue;
+        if (node.count > 0)
         {
-            if (is_cube[i])
+            for (int k = 0; k < node.count; ++k)
             {
-                // Find closest cube intersection
-                vec3 cube_position = objects_position[i];
-                vec3 cube_size = objects_size[i];
-                vec3 cube_min = cube_position - cube_size * 0.5;
-                vec3 cube_max = cube_position + cube_size * 0.5;
+                int index = node.left_first + k;
+                // Directions stay unnormalized so t is the same in both spaces
+                mat4 world_to_local = instances[index].world_to_local;
+                vec3 local_origin = (world_to_local * vec4(origin, 1.0)).xyz;
+                vec3 local_dir = (world_to_local * vec4(dir, 0.0)).xyz;
+                int triangle;
+                vec2 barycentric;
+                if (traceBlas(index, local_origin, local_dir, closest, triangle, barycentric))
+                {
+                    instance = index;
+                    hit_triangle = triangle;
+                    hit_barycentric = barycentric;
+                }
+            }
+        }
+        else if (stack_size + 2 <= BVH_STACK_SIZE)
+        {
+            stack[stack_size++] = node.left_first + 1;
+            stack[stack_size++] = node.left_first;
+        }
+    }
 
-                float tMin = (cube_min.x - rayOrigin.x) / rayDir.x;
-                float tMax = (cube_max.x - rayOrigin.x) / rayDir.x;
-                if (tMin > tMax) { float temp = tMin; tMin = tMax; tMax = temp; }
+    if (instance == -1)
+        return false;
 
-                float tyMin = (cube_min.y - rayOrigin.y) / rayDir.y;
-                float tyMax = (cube_max.y - rayOrigin.y) / rayDir.y;
-                if (tyMin > tyMax) { float temp = tyMin; tyMin = tyMax; tyMax = temp; }
+    MeshTriangle tri = mesh_triangles[hit_triangle];
+    vec3 local_normal = tri.n0.xyz * (1.0 - hit_barycentric.x - hit_barycentric.y) + tri.n1.xyz * hit_barycentric.x + tri.n2.xyz * hit_barycentric.y;
+    normal = normalize(transpose(mat3(instances[instance].world_to_local)) * local_normal);
+    if (dot(normal, dir) > 0.0)
+        normal = -normal;
+    return true;
+}
 
-                if ((tMin > tyMax) || (tyMin > tMax))
-                    continue;
+// Finds the closest object hit by the ray, returns its index or -1
+int findClosestObject(vec3 rayOrigin, vec3 rayDir, out float closestIntersection)
+{
+    closestIntersection = 9999.0;
+    int closestObjectIndex = -1;
 
-                if (tyMin > tMin)
-                    tMin = tyMin;
-                if (tyMax < tMax)
-                    tMax = tyMax;
+    for (int i = 0; i < num_objects; ++i)
+    {
+        if (objects[i].is_cube != 0)
+        {
+            // Find closest cube intersection, in the cube's local frame
+            vec4 cube_rotation = objectRotation(i);
+            vec3 localOrigin = quatRotateInverse(cube_rotation, rayOrigin - objectPosition(i));
+            vec3 localDir = quatRotateInverse(cube_rotation, rayDir);
+            vec3 cube_size = objects[i].size;
+            vec3 cube_min = -cube_size * 0.5;
+            vec3 cube_max = cube_size * 0.5;
 
-                float tzMin = (cube_min.z - rayOrigin.z) / rayDir.z;
-                float tzMax = (cube_max.z - rayOrigin.z) / rayDir.z;
-                if (tzMin > tzMax) { float temp = tzMin; tzMin = tzMax; tzMax = temp; }
+            float tMin = (cube_min.x - localOrigin.x) / localDir.x;
+            float tMax = (cube_max.x - localOrigin.x) / localDir.x;
+            if (tMin > tMax) { float temp = tMin; tMin = tMax; tMax = temp; }
 
-                if ((tMin > tzMax) || (tzMin > tMax))
-                    continue;
+            float tyMin = (cube_min.y - localOrigin.y) / localDir.y;
+            float tyMax = (cube_max.y - localOrigin.y) / localDir.y;
+            if (tyMin > tyMax) { float temp = tyMin; tyMin = tyMax; tyMax = temp; }
 
-                if (tzMin > tMin)
-                    tMin = tzMin;
-                if (tzMax < tMax)
-                    tMax = tzMax;
+            if ((tMin > tyMax) || (tyMin > tMax))
+                continue;
 
-                if (tMin < 0) tMin = tMax;
+            if (tyMin > tMin)
+                tMin = tyMin;
+            if (tyMax < tMax)
+                tMax = tyMax;
 
-                if (tMin > 0 && tMin < closestIntersection)
-                {
-                    vec3 hit_point = rayOrigin + rayDir * tMin;
+            float tzMin = (cube_min.z - localOrigin.z) / localDir.z;
+            float tzMax = (cube_max.z - localOrigin.z) / localDir.z;
+            if (tzMin > tzMax) { float temp = tzMin; tzMin = tzMax; tzMax = temp; }
 
-                    // Calculate the normal of the intersected face
-                    vec3 normal;
+            if ((tMin > tzMax) || (tzMin > tMax))
+                continue;
 
-                    if (abs(hit_point.x - cube_min.x) < 0.001) normal = vec3(-1, 0, 0);
-                    else if (abs(hit_point.x - cube_max.x) < 0.001) normal = vec3(1, 0, 0);
-                    else if (abs(hit_point.y - cube_min.y) < 0.001) normal = vec3(0, -1, 0);
-                    else if (abs(hit_point.y - cube_max.y) < 0.001) normal = vec3(0, 1, 0);
-                    else if (abs(hit_point.z - cube_min.z) < 0.001) normal = vec3(0, 0, -1);
-                    else if (abs(hit_point.z - cube_max.z) < 0.001) normal = vec3(0, 0, 1);
+            if (tzMin > tMin)
+                tMin = tzMin;
+            if (tzMax < tMax)
+                tMax = tzMax;
 
-                    // Use this hit information for shading
-                    closestIntersection = tMin;
-                    closestObjectIndex = i;
-                    objectRoughness = objects_roughness[i];
-                    isSphere = false; // Mark it as cube intersection
-                }
-            }
-            else
+            if (tMin < 0) tMin = tMax;
+
+            if (tMin > 0 && tMin < closestIntersection)
             {
-                // Find closest sphere intersection
-                vec3 sphere_position = objects_position[i];
-                float sphere_radius = objects_radius[i];
+                closestIntersection = tMin;
+                closestObjectIndex = i;
+            }
+        }
+        else
+        {
+            // Find closest sphere intersection
+            vec3 sphere_position = objectPosition(i);
+            float sphere_radius = objects[i].radius;
 
-                vec3 oc = rayOrigin - sphere_position;
-                float a = dot(rayDir, rayDir);
-                float b = 2.0 * dot(oc, rayDir);
-                float c = dot(oc, oc) - sphere_radius * sphere_radius;
-                float discriminant = b * b - 4.0 * a * c;
+            vec3 oc = rayOrigin - sphere_position;
+            float a = dot(rayDir, rayDir);
+            float b = 2.0 * dot(oc, rayDir);
+            float c = dot(oc, oc) - sphere_radius * sphere_radius;
+            float discriminant = b * b - 4.0 * a * c;
 
-                if (discriminant > 0.0)
+            if (discriminant > 0.0)
+            {
+                float temp = (-b - sqrt(discriminant)) / (2.0 * a);
+                // Rays travelling inside a volume or glass exit through the far side
+                if (temp <= 0.0 && (objects[i].is_volume != 0 || objects[i].is_glass != 0))
+                    temp = (-b + sqrt(discriminant)) / (2.0 * a);
+                if (temp > 0.0 && temp < closestIntersection)
                 {
-                    float temp = (-b - sqrt(discriminant)) / (2.0 * a);
-                    if (temp > 0.0 && temp < closestIntersection)
-                    {
-                        closestIntersection = temp;
-                        closestObjectIndex = i;
-                        objectRoughness = objects_roughness[i];
-                        isSphere = true;
-                    }
+                    closestIntersection = temp;
+                    closestObjectIndex = i;
                 }
             }
         }
+    }
+
+    return closestObjectIndex;
+}
+
+vec3 objectNormal(int i, vec3 hit_point)
+{
+    if (objects[i].is_cube == 0)
+        return normalize(hit_point - objectPosition(i));
+
+    vec3 local_point = objectLocalPoint(i, hit_point);
+    vec3 cube_min = -objects[i].size * 0.5;
+    vec3 cube_max = objects[i].size * 0.5;
+    vec3 normal = vec3(0.0, 1.0, 0.0);
+    if (abs(local_point.x - cube_min.x) < 0.001) normal = vec3(-1, 0, 0);
+    else if (abs(local_point.x - cube_max.x) < 0.001) normal = vec3(1, 0, 0);
+    else if (abs(local_point.y - cube_min.y) < 0.001) normal = vec3(0, -1, 0);
+    else if (abs(local_point.y - cube_max.y) < 0.001) normal = vec3(0, 1, 0);
+    else if (abs(local_point.z - cube_min.z) < 0.001) normal = vec3(0, 0, -1);
+    else if (abs(local_point.z - cube_max.z) < 0.001) normal = vec3(0, 0, 1);
+    return quatRotate(objectRotation(i), normal);
+}
+
+// Material of object i at hit_point with procedural textures applied
+void objectMaterial(int i, vec3 hit_point, out vec3 albedo, out float objectRoughness, out float emission)
+{
+    albedo = objects[i].color / 255.0;
+    objectRoughness = objects[i].roughness;
+    emission = objects[i].emission;
+
+    // Procedural textures are evaluated in object space
+    ivec3 textures = objects[i].textures;
+    vec3 local_point = objectLocalPoint(i, hit_point);
+    if (textures.x >= 0 && textures.x < num_texture_graphs)
+        albedo = evaluate_texture(textures.x, local_point);
+    if (textures.y >= 0 && textures.y < num_texture_graphs)
+        objectRoughness *= luminance(evaluate_texture(textures.y, local_point));
+    if (textures.z >= 0 && textures.z < num_texture_graphs)
+        emission *= luminance(evaluate_texture(textures.z, local_point));
+}
+
+// Participating media
+struct Medium
+{
+    vec3 sigma_s; // Scattering coefficient per channel
+    vec3 sigma_a; // Absorption coefficient per channel
+    float g;      // Henyey-Greenstein anisotropy
+};
+
+bool insideObject(int i, vec3 p)
+{
+    if (objects[i].is_cube != 0)
+        return all(lessThan(abs(objectLocalPoint(i, p)), objects[i].size * 0.5));
+    return length(p - objectPosition(i)) < objects[i].radius;
+}
+
+// Homogeneous medium at p: global fog plus every volume object containing p.
+// Segments between two surface hits never cross a volume boundary, so the
+// medium at any point of a segment is the medium of the whole segment.
+Medium mediumAt(vec3 p)
+{
+    Medium medium;
+    medium.sigma_s = fog_density * fog_albedo;
+    medium.sigma_a = fog_density * (vec3(1.0) - fog_albedo);
+    float weighted_g = fog_anisotropy * fog_density;
+    float total_weight = fog_density;
+
+    for (int i = 0; i < num_objects; ++i)
+    {
+        if (objects[i].is_volume != 0 && insideObject(i, p))
+        {
+            vec3 sigma_s = objects[i].medium.x * objects[i].color / 255.0;
+            medium.sigma_s += sigma_s;
+            medium.sigma_a += vec3(objects[i].medium.y);
+            weighted_g += objects[i].medium.z * objects[i].medium.x;
+            total_weight += objects[i].medium.x;
+        }
+    }
+
+    medium.g = total_weight > 0.0 ? weighted_g / total_weight : 0.0;
+    return medium;
+}
+
+float henyeyGreenstein(float cos_theta, float g)
+{
+    float denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    return (1.0 - g * g) / (4.0 * pi * denom * sqrt(denom));
+}
+
+// Samples a scattered direction around the propagation direction dir
+vec3 sampleHenyeyGreenstein(vec3 dir, float g, inout uint rngState)
+{
+    float u1 = random(rngState);
+    float u2 = random(rngState);
+    float cos_theta;
+    if (abs(g) < 0.001)
+        cos_theta = 1.0 - 2.0 * u1;
+    else
+    {
+        float sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+        cos_theta = clamp((1.0 + g * g - sq * sq) / (2.0 * g), -1.0, 1.0);
+    }
+    float sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    float phi = 2.0 * pi * u2;
+
+    vec3 w = normalize(dir);
+    vec3 a = abs(w.x) > 0.9 ? vec3(0, 1, 0) : vec3(1, 0, 0);
+    vec3 u = normalize(cross(a, w));
+    vec3 v = cross(w, u);
+    return normalize(u * cos(phi) * sin_theta + v * sin(phi) * sin_theta + w * cos_theta);
+}
+
+const int MEDIUM_NONE = 0;
+const int MEDIUM_SCATTERED = 1;
+const int MEDIUM_ABSORBED = 2;
+
+// Weighted delta tracking through a homogeneous chromatic medium segment of
+// length t_max. The majorant is the largest channel of sigma_t, the other
+// channels see null collisions and are reweighted instead.
+int deltaTrack(Medium medium, float t_max, inout uint rngState, inout vec3 contribution, out float t)
+{
+    vec3 sigma_t = medium.sigma_s + medium.sigma_a;
+    float majorant = max(sigma_t.x, max(sigma_t.y, sigma_t.z));
+    t = 0.0;
+    if (majorant <= 0.0)
+        return MEDIUM_NONE;
+
+    vec3 sigma_n = vec3(majorant) - sigma_t;
+    float p_scatter_weight = (medium.sigma_s.x + medium.sigma_s.y + medium.sigma_s.z) / 3.0;
+    float p_null_weight = (sigma_n.x + sigma_n.y + sigma_n.z) / 3.0;
+
+    for (int i = 0; i < 64; ++i)
+    {
+        t -= log(1.0 - random(rngState)) / majorant;
+        if (t >= t_max)
+            return MEDIUM_NONE;
+
+        // Pure absorbers never scatter, the path ends at the first real collision
+        if (p_scatter_weight + p_null_weight <= 0.0)
+            return MEDIUM_ABSORBED;
+
+        float p_scatter = p_scatter_weight / (p_scatter_weight + p_null_weight);
+        if (random(rngState) < p_scatter)
+        {
+            contribution *= medium.sigma_s / (majorant * p_scatter);
+            return MEDIUM_SCATTERED;
+        }
+        contribution *= sigma_n / (majorant * (1.0 - p_scatter));
+    }
+    return MEDIUM_ABSORBED;
+}
+
+// Ratio tracking estimate of the transmittance of a segment. Using twice the
+// largest sigma_t as majorant keeps grey media from collapsing to 0 or 1.
+vec3 ratioTrack(Medium medium, float t_max, inout uint rngState)
+{
+    vec3 sigma_t = medium.sigma_s + medium.sigma_a;
+    float majorant = 2.0 * max(sigma_t.x, max(sigma_t.y, sigma_t.z));
+    vec3 transmittance = vec3(1.0);
+    if (majorant <= 0.0)
+        return transmittance;
+
+    float t = 0.0;
+    for (int i = 0; i < 64; ++i)
+    {
+        t -= log(1.0 - random(rngState)) / majorant;
+        if (t >= t_max)
+            break;
+        transmittance *= vec3(1.0) - sigma_t / majorant;
+    }
+    return transmittance;
+}
+
+// Transmittance from origin to the light object `target` at distance t_end.
+// Volume objects are crossed segment by segment, anything else occludes.
+vec3 shadowTransmittance(vec3 origin, vec3 dir, float t_end, int target, inout uint rngState)
+{
+    // Meshes are always opaque
+    float t_mesh = t_end - 0.001;
+    int mesh_instance;
+    vec3 mesh_normal;
+    if (traceInstances(origin, dir, t_mesh, mesh_instance, mesh_normal))
+        return vec3(0.0);
+
+    vec3 transmittance = vec3(1.0);
+    float t = 0.0;
+    for (int crossing = 0; crossing < 8; ++crossing)
+    {
+        float t_hit;
+        int hit = findClosestObject(origin + dir * t, dir, t_hit);
+        float segment_end = t_end;
+        if (hit != -1 && hit != target && t + t_hit < t_end - 0.001)
+        {
+            if (objects[hit].is_volume == 0)
+                return vec3(0.0);
+            segment_end = t + t_hit;
+        }
+        Medium medium = mediumAt(origin + dir * (t + segment_end) * 0.5);
+        transmittance *= ratioTrack(medium, segment_end - t, rngState);
+        if (segment_end >= t_end)
+            break;
+        t = segment_end + 0.001;
+    }
+    return transmittance;
+}
+
+// Average radiance the surface shading below adds when a path hits emissive
+// object i: only the diffuse branch (taken with probability roughness) emits,
+// and it scales emission by the albedo twice.
+vec3 emittedRadiance(int i, vec3 p)
+{
+    vec3 albedo;
+    float objectRoughness;
+    float emission;
+    objectMaterial(i, p, albedo, objectRoughness, emission);
+    return objectRoughness * albedo * albedo * emission;
+}
+
+bool isSphereLight(int i)
+{
+    return objects[i].is_cube == 0 && objects[i].is_volume == 0 && objects[i].is_glass == 0 && objects[i].emission > 0.0 && objects[i].radius > 0.0;
+}
+
+// Next event estimation from a medium scattering point towards one emissive
+// sphere, picked uniformly. Paths leaving this vertex skip sphere emitters so
+// their light is not counted twice.
+vec3 sampleSphereLight(vec3 p, vec3 dir, float g, inout uint rngState)
+{
+    int light_count = 0;
+    for (int i = 0; i < num_objects; ++i)
+        if (isSphereLight(i)) light_count++;
+    if (light_count == 0)
+        return vec3(0.0);
+
+    int pick = min(int(random(rngState) * float(light_count)), light_count - 1);
+    int light = -1;
+    for (int i = 0; i < num_objects; ++i)
+    {
+        if (isSphereLight(i))
+        {
+            if (pick == 0) { light = i; break; }
+            pick--;
+        }
+    }
+
+    vec3 to_center = objectPosition(light) - p;
+    float dist2 = dot(to_center, to_center);
+    float radius = objects[light].radius;
+    if (dist2 <= radius * radius)
+        return vec3(0.0);
+
+    // Uniform sampling of the cone subtended by the sphere
+    float cos_max = sqrt(1.0 - radius * radius / dist2);
+    float cos_theta = 1.0 - random(rngState) * (1.0 - cos_max);
+    float sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    float phi = 2.0 * pi * random(rngState);
+    vec3 w = normalize(to_center);
+    vec3 a = abs(w.x) > 0.9 ? vec3(0, 1, 0) : vec3(1, 0, 0);
+    vec3 u = normalize(cross(a, w));
+    vec3 v = cross(w, u);
+    vec3 light_dir = normalize(u * cos(phi) * sin_theta + v * sin(phi) * sin_theta + w * cos_theta);
+    float pdf = 1.0 / (2.0 * pi * (1.0 - cos_max));
+
+    vec3 oc = p - objectPosition(light);
+    float b = dot(oc, light_dir);
+    float c = dot(oc, oc) - radius * radius;
+    float t_light = -b - sqrt(max(0.0, b * b - c));
+
+    vec3 transmittance = shadowTransmittance(p, light_dir, t_light, light, rngState);
+    float phase = henyeyGreenstein(dot(dir, light_dir), g);
+    return transmittance * phase * emittedRadiance(light, p + light_dir * t_light) * float(light_count) / pdf;
+}
+
+// Distance along dir from a point inside object i to its boundary
+float exitDistance(int i, vec3 origin, vec3 dir)
+{
+    if (objects[i].is_cube != 0)
+    {
+        vec3 half_size = objects[i].size * 0.5;
+        vec3 local_origin = objectLocalPoint(i, origin);
+        vec3 inv_dir = 1.0 / quatRotateInverse(objectRotation(i), dir);
+        vec3 t0 = (-half_size - local_origin) * inv_dir;
+        vec3 t1 = (half_size - local_origin) * inv_dir;
+        vec3 t_far = max(t0, t1);
+        return max(0.0, min(t_far.x, min(t_far.y, t_far.z)));
+    }
+    vec3 oc = origin - objectPosition(i);
+    float b = dot(oc, dir);
+    float c = dot(oc, oc) - objects[i].radius * objects[i].radius;
+    return max(0.0, -b + sqrt(max(0.0, b * b - c)));
+}
+
+// Single scattering albedo that gives the surface albedo after multiple
+// scattering (van de Hulst inversion, as used by Cycles)
+vec3 subsurfaceAlbedo(vec3 albedo)
+{
+    vec3 a = clamp(albedo, vec3(0.0), vec3(0.999));
+    vec3 s = 4.09712 + 4.20863 * a - sqrt(9.59217 + 41.6808 * a + 17.7126 * a * a);
+    return vec3(1.0) - s * s;
+}
+
+// Random walk inside object i starting at position/direction. Returns false if
+// the walk was absorbed, otherwise position is the exit point and direction
+// the outgoing direction. Each step samples one channel's free path and
+// weights by the average pdf over all channels (spectral MIS).
+bool subsurfaceWalk(int i, vec3 albedo, inout vec3 position, inout vec3 direction, inout vec3 contribution, inout uint rngState)
+{
+    vec3 sigma_t = 1.0 / max(objects[i].subsurface_radius, vec3(0.0001));
+    vec3 sigma_s = sigma_t * subsurfaceAlbedo(albedo);
+
+    for (int step = 0; step < 64; ++step)
+    {
+        int channel = min(int(random(rngState) * 3.0), 2);
+        float t = -log(1.0 - random(rngState)) / sigma_t[channel];
+        float t_exit = exitDistance(i, position, direction);
+
+        if (t >= t_exit)
+        {
+            vec3 transmittance = exp(-sigma_t * t_exit);
+            contribution *= transmittance / ((transmittance.x + transmittance.y + transmittance.z) / 3.0);
+            position += direction * t_exit;
+            vec3 normal = objectNormal(i, position);
+            position += normal * 0.001;
+            direction = normalize(normal + random_in_unit_sphere(rngState));
+            return true;
+        }
+
+        vec3 transmittance = exp(-sigma_t * t);
+        vec3 pdf = sigma_t * transmittance;
+        contribution *= sigma_s * transmittance / ((pdf.x + pdf.y + pdf.z) / 3.0);
+        position += direction * t;
+        direction = random_in_unit_sphere(rngState);
+
+        // Terminate walks that lost almost all their energy
+        if (max(contribution.x, max(contribution.y, contribution.z)) < 0.001)
+            return false;
+    }
+    return false;
+}
+
+// Spectral rendering
+
+// Cauchy dispersion n = A + B / lambda^2, with A chosen so that ior is the
+// index at the sodium D line (589.3nm)
+float cauchyIor(float ior, float dispersion, float wavelength)
+{
+    float lambda_um = wavelength * 0.001;
+    return ior - dispersion / (0.5893 * 0.5893) + dispersion / (lambda_um * lambda_um);
+}
+
+float cieLobe(float x, float mu, float sigma1, float sigma2)
+{
+    float t = (x - mu) / (x < mu ? sigma1 : sigma2);
+    return exp(-0.5 * t * t);
+}
+
+// CIE 1931 matching functions (Wyman, Sloan and Shirley fit) converted to
+// linear sRGB and normalized so that the average over 380-780nm is 1 per
+// channel. A uniformly sampled wavelength weighted by this reproduces RGB.
+vec3 wavelengthToRgbWeight(float wavelength)
+{
+    vec3 xyz;
+    xyz.x = 1.056 * cieLobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * cieLobe(wavelength, 442.0, 16.0, 26.7) - 0.065 * cieLobe(wavelength, 501.1, 20.4, 26.2);
+    xyz.y = 0.821 * cieLobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * cieLobe(wavelength, 530.9, 16.3, 31.1);
+    xyz.z = 1.217 * cieLobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * cieLobe(wavelength, 459.0, 26.0, 13.8);
+    vec3 rgb = vec3(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z);
+    return max(rgb, vec3(0.0)) / vec3(0.44046, 0.28847, 0.27324);
+}
+
+vec3 calculateLightContribution(vec3 rayOrigin, vec3 rayDir, inout uint rngState, vec3 contribution, float wavelength)
+{
+    vec3 light = vec3(0.0);
+    bool after_medium_scatter = false;
+    int boundary_crossings = 0;
+
+    for (int bounce = 0; bounce < max_bounces; ++bounce)
+    {
+        // Russian roulette on the path throughput, surviving paths are reweighted
+        if (bounce > 0 && bounce >= rr_min_depth)
+        {
+            float survival = clamp(max(contribution.x, max(contribution.y, contribution.z)), 0.05, 1.0);
+            if (random(rngState) >= survival)
+                break;
+            contribution /= survival;
+        }
+
+        // Find closest object intersection
+        float closestIntersection;
+        int closestObjectIndex = findClosestObject(rayOrigin, rayDir, closestIntersection);
+        int hitInstance;
+        vec3 instanceNormal;
+        if (traceInstances(rayOrigin, rayDir, closestIntersection, hitInstance, instanceNormal))
+            closestObjectIndex = -1;
+
+        // Scattering in fog and volume interiors before reaching the surface
+        bool is_hit = closestObjectIndex != -1 || hitInstance != -1;
+        float segment_end = is_hit ? closestIntersection : fog_extent;
+        Medium medium = mediumAt(rayOrigin + rayDir * segment_end * 0.5);
+        float t_event;
+        int event = deltaTrack(medium, segment_end, rngState, contribution, t_event);
+        if (event == MEDIUM_ABSORBED)
+            break;
+        if (event == MEDIUM_SCATTERED)
+        {
+            rayOrigin = rayOrigin + rayDir * t_event;
+            light += contribution * sampleSphereLight(rayOrigin, rayDir, medium.g, rngState);
+            rayDir = sampleHenyeyGreenstein(rayDir, medium.g, rngState);
+            after_medium_scatter = true;
+            continue;
+        }
 
         // Handle intersection and shading
-        if (closestObjectIndex != -1)
+        if (is_hit)
         {
-            vec3 hit_point;
+            vec3 hit_point = rayOrigin + rayDir * closestIntersection;
             vec3 normal;
             vec3 albedo;
+            float objectRoughness;
             float emission;
 
-            if (isSphere)
+            if (hitInstance != -1)
             {
-                vec3 sphere_position = objects_position[closestObjectIndex];
-                float sphere_radius = objects_radius[closestObjectIndex];
-                hit_point = rayOrigin + rayDir * closestIntersection;
-                normal = normalize(hit_point - sphere_position);
-                albedo = objects_color[closestObjectIndex] / 255.0;
-                emission = objects_emission[closestObjectIndex];
+                // Mesh instances are opaque with a plain material
+                normal = instanceNormal;
+                albedo = instances[hitInstance].color / 255.0;
+                objectRoughness = instances[hitInstance].roughness;
+                emission = instances[hitInstance].emission;
+                after_medium_scatter = false;
             }
             else
             {
-                vec3 cube_position = objects_position[closestObjectIndex];
-                vec3 cube_size = objects_size[closestObjectIndex];
-                vec3 cube_min = cube_position - cube_size * 0.5;
-                vec3 cube_max = cube_position + cube_size * 0.5;
-                hit_point = rayOrigin + rayDir * closestIntersection;
-
-                // Calculate normal for cube
-                if (abs(hit_point.x - cube_min.x) < 0.001) normal = vec3(-1, 0, 0);
-                else if (abs(hit_point.x - cube_max.x) < 0.001) normal = vec3(1, 0, 0);
-                else if (abs(hit_point.y - cube_min.y) < 0.001) normal = vec3(0, -1, 0);
-                else if (abs(hit_point.y - cube_max.y) < 0.001) normal = vec3(0, 1, 0);
-                else if (abs(hit_point.z - cube_min.z) < 0.001) normal = vec3(0, 0, -1);
-                else if (abs(hit_point.z - cube_max.z) < 0.001) normal = vec3(0, 0, 1);
-
-                albedo = objects_color[closestObjectIndex] / 255.0;
-                emission = objects_emission[closestObjectIndex];
+                // Volume boundaries are index matched, the ray continues unchanged
+                if (objects[closestObjectIndex].is_volume != 0 && objects[closestObjectIndex].is_glass == 0)
+                {
+                    rayOrigin = hit_point + rayDir * 0.001;
+                    if (boundary_crossings++ < 16)
+                        bounce--;
+                    continue;
+                }
+
+                normal = objectNormal(closestObjectIndex, hit_point);
+                objectMaterial(closestObjectIndex, hit_point, albedo, objectRoughness, emission);
+
+                // Sphere lights were already sampled from the last medium vertex
+                if (after_medium_scatter && isSphereLight(closestObjectIndex))
+                    emission = 0.0;
+                after_medium_scatter = false;
+
+                // Dielectric glass, tinted by the albedo when entering
+                if (objects[closestObjectIndex].is_glass != 0)
+                {
+                    float ior = objects[closestObjectIndex].ior;
+                    if (is_spectral)
+                        ior = cauchyIor(ior, objects[closestObjectIndex].dispersion, wavelength);
+                    bool entering = dot(rayDir, normal) < 0.0;
+                    vec3 facing_normal = entering ? normal : -normal;
+                    float ni_over_nt = entering ? 1.0 / ior : ior;
+                    float cosine = min(-dot(normalize(rayDir), facing_normal), 1.0);
+                    vec3 refracted;
+                    if (refract(rayDir, facing_normal, ni_over_nt, refracted) && random(rngState) >= schlick(cosine, ior))
+                    {
+                        rayDir = normalize(refracted);
+                        rayOrigin = hit_point - facing_normal * 0.001;
+                        if (entering)
+                            contribution *= albedo;
+                    }
+                    else
+                    {
+                        rayDir = reflect(rayDir, facing_normal);
+                        rayOrigin = hit_point + facing_normal * 0.001;
+                    }
+                    continue;
+                }
+
+                // Subsurface scattering, the walk replaces the diffuse bounce
+                float subsurface = objects[closestObjectIndex].subsurface;
+                if (subsurface > 0.0 && random(rngState) < subsurface)
+                {
+                    vec3 walk_position = hit_point - normal * 0.001;
+                    vec3 walk_direction = normalize(-normal + random_in_unit_sphere(rngState));
+                    if (!subsurfaceWalk(closestObjectIndex, albedo, walk_position, walk_direction, contribution, rngState))
+                        break;
+                    rayOrigin = walk_position;
+                    rayDir = walk_direction;
+                    continue;
+                }
             }
 
             // Calculate reflection direction based on roughness
@@ -320,46 +1221,85 @@ vec3 calculateLightContribution(vec3 rayOrigin, vec3 rayDir, inout uint rngState
 
 void main()
 {
-    ivec2 texel_coords = ivec2(gl_GlobalInvocationID.xy);
-    vec2 screen_resolution = vec2(imageSize(screen));
-    vec2 normalized_coords = (vec2(texel_coords) + vec2(0.5)) / screen_resolution * 2.0 - 1.0;
+    ivec2 texel_coords = ivec2(gl_GlobalInvocationID.xy) + tile_offset;
+    ivec2 image_size = imageSize(screen);
+    // Dispatches are rounded up to whole work groups, the excess would
+    // trace pixels of the next tile or outside the image
+    ivec2 last = min(tile_end, image_size);
+    if (texel_coords.x >= last.x || texel_coords.y >= last.y)
+        return;
+    vec2 screen_resolution = vec2(image_size);
     float aspect_ratio = screen_resolution.x / screen_resolution.y;
 
-    vec3 initial_rayDir;
-    vec3 initial_rayOrigin;
-
-    if (is_fisheye) {
-        // Fisheye camera effect using a polynomial function
-        float r = length(normalized_coords);
-        float theta = atan(r);
-        float fisheye_factor = 1.0 + (r * r) * 0.2; // adjust this value to control the fisheye effect
-        vec2 distorted_coords = normalized_coords * fisheye_factor;
-
-        // Compute ray direction using camera vectors with FOV adjustment
-        float scale = tan(fov * 0.5);
-        initial_rayDir = normalize(camera_front + distorted_coords.x * aspect_ratio * scale * camera_right + distorted_coords.y * scale * camera_up);
-
-        // Rotate ray direction to match camera rotation
-        initial_rayDir = normalize(dot(initial_rayDir, camera_right) * camera_right + dot(initial_rayDir, camera_up) * camera_up + dot(initial_rayDir, camera_front) * camera_front);
-
-        initial_rayOrigin = camera_pos;
-    } else {
-        // Normal rendering without fisheye effect
-        float scale = tan(fov * 0.5);
-        initial_rayDir = normalize(camera_front + normalized_coords.x * aspect_ratio * scale * camera_right + normalized_coords.y * scale * camera_up);
-        initial_rayOrigin = camera_pos;
+    vec3 accumulated_light = vec3(0.0);
+    vec4 prevColor = is_accumulation ? imageLoad(screen, texel_coords) : vec4(0.0);
+    vec4 stats = is_accumulation ? imageLoad(variance_image, texel_coords) : vec4(0.0);
+
+    // Adaptive sampling: converged pixels keep their value, noisy pixels trace
+    // more samples in proportion to their relative error
+    int sample_count = samples_per_pixel;
+    if (is_adaptive && is_accumulation && prevColor.a >= float(adaptive_min_samples))
+    {
+        if (stats.z < adaptive_threshold)
+            return;
+        sample_count = samples_per_pixel * clamp(int(stats.z / adaptive_threshold), 1, adaptive_max_factor);
     }
 
-    vec3 accumulated_light = vec3(0.0);
-    uint rngState = (uint(gl_GlobalInvocationID.x) * 1973u + uint(gl_GlobalInvocationID.y) * 9277u + uint(frameNumber) * 26699u + uint(currentTime * 1000.0));
+    uint rngState = (uint(texel_coords.x) * 1973u + uint(texel_coords.y) * 9277u + uint(frameNumber) * 26699u + uint(currentTime * 1000.0));
 
-    // Depth of Field (DoF) calculations
-    vec3 focal_point = initial_rayOrigin + initial_rayDir * focal_length;
+    // Sample indices continue from the samples already accumulated in this pixel
+    uint first_sample = is_accumulation ? uint(prevColor.a) : uint(frameNumber) * uint(samples_per_pixel);
+    uint pixel_seed = pcgHash(uint(texel_coords.x) ^ pcgHash(uint(texel_coords.y)));
 
-    for (int i = 0; i < num_motion_blur_samples; ++i)
+    for (int i = 0; i < sample_count; ++i)
     {
-        // Motion blur: Jitter ray origin and direction based on camera velocity
-        float t = random(rngState);
+        // Pixel jitter, shutter time and wavelength come from the low discrepancy
+        // sequence, the rest of the path uses PCG seeded by the sample
+        vec4 u;
+        if (is_low_discrepancy)
+        {
+            uint sample_index = first_sample + uint(i);
+            u = sobolSample(sample_index, pixel_seed);
+            rngState = pcgHash(hashCombine(pixel_seed, sample_index));
+        }
+        else
+        {
+            u = vec4(random(rngState), random(rngState), random(rngState), random(rngState));
+        }
+        vec2 normalized_coords = (vec2(texel_coords) + u.xy) / screen_resolution * 2.0 - 1.0;
+
+        vec3 initial_rayDir;
+        vec3 initial_rayOrigin;
+
+        if (is_fisheye) {
+            // Fisheye camera effect using a polynomial function
+            float r = length(normalized_coords);
+            float theta = atan(r);
+            float fisheye_factor = 1.0 + (r * r) * 0.2; // adjust this value to control the fisheye effect
+            vec2 distorted_coords = normalized_coords * fisheye_factor;
+
+            // Compute ray direction using camera vectors with FOV adjustment
+            float scale = tan(fov * 0.5);
+            initial_rayDir = normalize(camera_front + distorted_coords.x * aspect_ratio * scale * camera_right + distorted_coords.y * scale * camera_up);
+
+            // Rotate ray direction to match camera rotation
+            initial_rayDir = normalize(dot(initial_rayDir, camera_right) * camera_right + dot(initial_rayDir, camera_up) * camera_up + dot(initial_rayDir, camera_front) * camera_front);
+
+            initial_rayOrigin = camera_pos;
+        } else {
+            // Normal rendering without fisheye effect
+            float scale = tan(fov * 0.5);
+            initial_rayDir = normalize(camera_front + normalized_coords.x * aspect_ratio * scale * camera_right + normalized_coords.y * scale * camera_up);
+            initial_rayOrigin = camera_pos;
+        }
+
+        // Depth of Field (DoF) calculations
+        vec3 focal_point = initial_rayOrigin + initial_rayDir * focal_length;
+
+        // Motion blur: objects move to the ray's shutter time, the camera
+        // jitters its origin based on camera velocity
+        ray_time = mix(shutter_open, shutter_close, u.z);
+        float t = u.z;
         vec3 rayOrigin = initial_rayOrigin + (t * camera_velocity) / 2;
         vec3 rayDir = normalize(focal_point - rayOrigin);
         vec3 aperture_offset = aperture * random_in_unit_sphere(rngState);
@@ -367,30 +1307,36 @@ void main()
         rayDir = normalize(focal_point - rayOrigin);
 
         // Calculate light contribution (including glass handling)
-        vec3 light = calculateLightContribution(rayOrigin, rayDir, rngState, vec3(1.0));
+        float wavelength = 589.3;
+        if (is_spectral)
+            wavelength = 380.0 + 400.0 * u.w;
+        vec3 light = calculateLightContribution(rayOrigin, rayDir, rngState, vec3(1.0), wavelength);
+        if (is_spectral)
+            light *= wavelengthToRgbWeight(wavelength);
         accumulated_light += light;
+
+        // Welford update of the luminance mean and variance
+        stats.w += 1.0;
+        float delta = luminance(light) - stats.x;
+        stats.x += delta / stats.w;
+        stats.y += delta * (luminance(light) - stats.x);
     }
 
+    // Standard error of the mean relative to the pixel brightness
+    float variance = stats.y / max(stats.w - 1.0, 1.0);
+    stats.z = sqrt(variance / stats.w) / max(stats.x, 0.01);
+    imageStore(variance_image, texel_coords, stats);
+
     // Average the light contributions from all samples
-    vec3 final_light = accumulated_light / float(num_motion_blur_samples);
+    vec3 final_light = accumulated_light / float(max(sample_count, 1));
 
     // Apply bloom
     float bloomThreshold = 0.8;
     float bloomIntensity = 1.0;
     applyBloom(final_light, final_light, bloomThreshold, bloomIntensity);
 
-    if (is_accumulation)
-    {
-        vec4 prevColor = imageLoad(screen, texel_coords);
-        float numFrames = prevColor.a;
-        numFrames += 1.0;
-        vec3 accumulatedColor = (prevColor.rgb * prevColor.a + final_light) / numFrames;
-        // Output final color to screen texture with accumulation
-        imageStore(screen, texel_coords, vec4(accumulatedColor, numFrames));
-    }
-    else
-    {
-        // Directly output the current frame color
-        imageStore(screen, texel_coords, vec4(final_light, 1.0));
-    }
+    // Weight by sample count so dispatches with different sample counts average correctly
+    float numSamples = prevColor.a + float(sample_count);
+    vec3 accumulatedColor = (prevColor.rgb * prevColor.a + final_light * float(sample_count)) / numSamples;
+    imageStore(screen, texel_coords, vec4(accumulatedColor, numSamples));
 }
@@ -2,6 +2,25 @@
 in vec2 tex_coords;
 out vec4 frag_color;
 uniform sampler2D screen_texture;
+uniform sampler2D variance_texture; // see variance_image in compute_shader.glsl
+uniform int display_mode; // 0 color, 1 sample count heatmap, 2 relative error heatmap
+uniform float adaptive_threshold;
+
+// Blue to red ramp for t in [0,1]
+vec3 heatmap(float t)
+{
+    t = clamp(t, 0.0, 1.0);
+    return clamp(vec3(1.5 - abs(4.0 * t - 3.0), 1.5 - abs(4.0 * t - 2.0), 1.5 - abs(4.0 * t - 1.0)), 0.0, 1.0);
+}
+
 void main() {
-    frag_color = texture(screen_texture, tex_coords);
-}
\ No newline at end of file
+    vec4 stats = texture(variance_texture, tex_coords);
+    if (display_mode == 1) {
+        // Log scale so both a few and thousands of samples are visible
+        frag_color = vec4(heatmap(log2(1.0 + stats.w) / 14.0), 1.0);
+    } else if (display_mode == 2) {
+        frag_color = vec4(heatmap(stats.z / (4.0 * adaptive_threshold)), 1.0);
+    } else {
+        frag_color = texture(screen_texture, tex_coords);
+    }
+}
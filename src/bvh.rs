@@ -0,0 +1,208 @@
+// Bounding volume hierarchy over axis aligned boxes, used both for the
+// triangles of a mesh (BLAS) and for the mesh instances of a scene (TLAS).
+// Nodes are stored flat in the layout of `BvhNode` in compute_shader.glsl.
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Aabb {
+            min: [f32::MAX; 3],
+            max: [f32::MIN; 3],
+        }
+    }
+
+    pub fn from_points(points: &[[f32; 3]]) -> Self {
+        let mut aabb = Aabb::empty();
+        for point in points {
+            aabb.grow(*point);
+        }
+        aabb
+    }
+
+    pub fn grow(&mut self, point: [f32; 3]) {
+        for ((min, max), value) in self.min.iter_mut().zip(self.max.iter_mut()).zip(point) {
+            *min = min.min(value);
+            *max = max.max(value);
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        if other.is_empty() {
+            return *self;
+        }
+        let mut aabb = *self;
+        aabb.grow(other.min);
+        aabb.grow(other.max);
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min[0] > self.max[0] || self.min[1] > self.max[1] || self.min[2] > self.max[2]
+    }
+
+    pub fn centroid(&self) -> [f32; 3] {
+        [
+            (self.min[0] + self.max[0]) * 0.5,
+            (self.min[1] + self.max[1]) * 0.5,
+            (self.min[2] + self.max[2]) * 0.5,
+        ]
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = [self.max[0] - self.min[0], self.max[1] - self.min[1], self.max[2] - self.min[2]];
+        2.0 * (d[0] * d[1] + d[1] * d[2] + d[2] * d[0])
+    }
+
+    // Bounds of the box after a column major affine transform
+    pub fn transformed(&self, matrix: &[[f32; 4]; 4]) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for corner in 0..8 {
+            let p = [
+                if corner & 1 == 0 { self.min[0] } else { self.max[0] },
+                if corner & 2 == 0 { self.min[1] } else { self.max[1] },
+                if corner & 4 == 0 { self.min[2] } else { self.max[2] },
+            ];
+            let mut world = [0.0; 3];
+            for row in 0..3 {
+                world[row] = matrix[0][row] * p[0] + matrix[1][row] * p[1] + matrix[2][row] * p[2] + matrix[3][row];
+            }
+            aabb.grow(world);
+        }
+        aabb
+    }
+}
+
+// Interior nodes have count 0 and their children at left_first and
+// left_first + 1, leaves cover `count` primitives starting at left_first
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    pub left_first: i32,
+    pub max: [f32; 3],
+    pub count: i32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    // Primitive indices in leaf order, callers reorder their primitives by this
+    pub order: Vec<usize>,
+}
+
+impl Bvh {
+    // Builds with binned surface area heuristic splits
+    pub fn build(boxes: &[Aabb]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(boxes.len() * 2),
+            order: (0..boxes.len()).collect(),
+        };
+        if boxes.is_empty() {
+            return bvh;
+        }
+        bvh.nodes.push(BvhNode::default());
+        bvh.subdivide(boxes, 0, 0, boxes.len());
+        bvh
+    }
+
+    fn subdivide(&mut self, boxes: &[Aabb], node: usize, first: usize, count: usize) {
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &i in self.order[first..first + count].iter() {
+            bounds = bounds.union(&boxes[i]);
+            centroid_bounds.grow(boxes[i].centroid());
+        }
+        self.nodes[node] = BvhNode {
+            min: bounds.min,
+            left_first: first as i32,
+            max: bounds.max,
+            count: count as i32,
+        };
+        if count <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        let (axis, split, cost) = match self.find_split(boxes, first, count, &centroid_bounds) {
+            Some(split) => split,
+            None => return,
+        };
+        if cost >= count as f32 * bounds.surface_area() {
+            return;
+        }
+
+        // Partition primitives by bin
+        let extent = centroid_bounds.max[axis] - centroid_bounds.min[axis];
+        let (left, right): (Vec<usize>, Vec<usize>) = self.order[first..first + count]
+            .iter()
+            .partition(|&&i| bin_index(boxes[i].centroid()[axis], centroid_bounds.min[axis], extent) < split);
+        let left_count = left.len();
+        if left_count == 0 || left_count == count {
+            return;
+        }
+        self.order[first..first + left_count].copy_from_slice(&left);
+        self.order[first + left_count..first + count].copy_from_slice(&right);
+
+        let left_child = self.nodes.len();
+        self.nodes.push(BvhNode::default());
+        self.nodes.push(BvhNode::default());
+        self.nodes[node].left_first = left_child as i32;
+        self.nodes[node].count = 0;
+        self.subdivide(boxes, left_child, first, left_count);
+        self.subdivide(boxes, left_child + 1, first + left_count, count - left_count);
+    }
+
+    // Returns the axis, the first bin of the right side and the SAH cost
+    fn find_split(&self, boxes: &[Aabb], first: usize, count: usize, centroid_bounds: &Aabb) -> Option<(usize, usize, f32)> {
+        let mut best: Option<(usize, usize, f32)> = None;
+        for axis in 0..3 {
+            let extent = centroid_bounds.max[axis] - centroid_bounds.min[axis];
+            if extent <= 0.0 {
+                continue;
+            }
+            let mut bin_bounds = [Aabb::empty(); BIN_COUNT];
+            let mut bin_counts = [0usize; BIN_COUNT];
+            for &i in self.order[first..first + count].iter() {
+                let bin = bin_index(boxes[i].centroid()[axis], centroid_bounds.min[axis], extent);
+                bin_bounds[bin] = bin_bounds[bin].union(&boxes[i]);
+                bin_counts[bin] += 1;
+            }
+
+            // Sweep from the right to get the cost of the right side of each split
+            let mut right_costs = [0.0; BIN_COUNT];
+            let mut right_bounds = Aabb::empty();
+            let mut right_count = 0;
+            for bin in (1..BIN_COUNT).rev() {
+                right_bounds = right_bounds.union(&bin_bounds[bin]);
+                right_count += bin_counts[bin];
+                right_costs[bin] = right_count as f32 * right_bounds.surface_area();
+            }
+
+            let mut left_bounds = Aabb::empty();
+            let mut left_count = 0;
+            for split in 1..BIN_COUNT {
+                left_bounds = left_bounds.union(&bin_bounds[split - 1]);
+                left_count += bin_counts[split - 1];
+                let cost = left_count as f32 * left_bounds.surface_area() + right_costs[split];
+                if best.map_or(true, |(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, split, cost));
+                }
+            }
+        }
+        best
+    }
+}
+
+fn bin_index(centroid: f32, min: f32, extent: f32) -> usize {
+    (((centroid - min) / extent * BIN_COUNT as f32) as usize).min(BIN_COUNT - 1)
+}
@@ -63,6 +63,20 @@ impl Camera {
         perspective(self.fov, aspect_ratio, 0.1, 1000.0) * look_at(self.position, self.position + self.front, self.up)
     }
 
+    // Direction of the ray through a window pixel, the same ray the compute shader
+    // traces there. The rendered image is stretched over the window, so its own
+    // aspect ratio is used.
+    pub fn screen_ray(&self, x: f32, y: f32, width: f32, height: f32, aspect_ratio: f32, is_fisheye: bool) -> Vec3 {
+        let (mut u, mut v) = (x / width * 2.0 - 1.0, 1.0 - y / height * 2.0);
+        if is_fisheye {
+            let fisheye_factor = 1.0 + (u * u + v * v) * 0.2;
+            u *= fisheye_factor;
+            v *= fisheye_factor;
+        }
+        let scale = (self.fov * 0.5).tan();
+        glm::normalize(self.front + self.right * (u * aspect_ratio * scale) + self.up * (v * scale))
+    }
+
     pub fn process_keyboard(&mut self, direction: CameraMovement, delta_time: f32) {
         let acceleration = self.acceleration * delta_time;
 
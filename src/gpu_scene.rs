@@ -0,0 +1,253 @@
+use egui_sdl2_gl::gl;
+use egui_sdl2_gl::gl::types::*;
+
+use crate::bvh::{Aabb, Bvh, BvhNode};
+use crate::mesh::{Instance, Mesh, MeshTriangle};
+use crate::object::Object;
+
+// Shader storage bindings, see the buffer blocks in compute_shader.glsl
+pub const OBJECT_BUFFER_BINDING: GLuint = 1;
+pub const TRIANGLE_BUFFER_BINDING: GLuint = 3;
+pub const BLAS_BUFFER_BINDING: GLuint = 4;
+pub const INSTANCE_BUFFER_BINDING: GLuint = 5;
+pub const TLAS_BUFFER_BINDING: GLuint = 6;
+
+// std430 layout of one object, must match `SceneObject` in compute_shader.glsl.
+// Every vec3 is followed by a scalar so each row fills 16 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GpuObject {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub size: [f32; 3],
+    pub roughness: f32,
+    pub color: [f32; 3],
+    pub emission: f32,
+    pub medium: [f32; 3],
+    pub ior: f32,
+    pub subsurface_radius: [f32; 3],
+    pub subsurface: f32,
+    pub textures: [i32; 3],
+    pub dispersion: f32,
+    pub is_cube: i32,
+    pub is_glass: i32,
+    pub is_volume: i32,
+    pub _padding: i32,
+    pub velocity: [f32; 3],
+    pub _padding1: f32,
+    pub angular_velocity: [f32; 3],
+    pub _padding2: f32,
+    // (x, y, z, w), Object stores w first
+    pub orientation: [f32; 4],
+}
+
+impl GpuObject {
+    pub fn from_object(object: &Object) -> Self {
+        GpuObject {
+            position: object.position,
+            radius: object.radius,
+            size: object.size,
+            roughness: object.roughness,
+            color: object.color,
+            emission: object.emission,
+            medium: object.medium,
+            ior: object.ior,
+            subsurface_radius: object.subsurface_radius,
+            subsurface: object.subsurface,
+            textures: object.textures,
+            dispersion: object.dispersion,
+            is_cube: object.is_cube as i32,
+            is_glass: object.is_glass as i32,
+            is_volume: object.is_volume as i32,
+            _padding: 0,
+            velocity: object.velocity,
+            _padding1: 0.0,
+            angular_velocity: object.angular_velocity,
+            _padding2: 0.0,
+            orientation: [object.orientation[1], object.orientation[2], object.orientation[3], object.orientation[0]],
+        }
+    }
+}
+
+// Must match `MeshTriangle` in compute_shader.glsl, w components are unused
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GpuTriangle {
+    pub vertices: [[f32; 4]; 3],
+    pub normals: [[f32; 4]; 3],
+}
+
+impl GpuTriangle {
+    pub fn from_triangle(triangle: &MeshTriangle) -> Self {
+        let v = triangle.vertices;
+        let n = triangle.normals;
+        GpuTriangle {
+            vertices: [
+                [v[0][0], v[0][1], v[0][2], 0.0],
+                [v[1][0], v[1][1], v[1][2], 0.0],
+                [v[2][0], v[2][1], v[2][2], 0.0],
+            ],
+            normals: [
+                [n[0][0], n[0][1], n[0][2], 0.0],
+                [n[1][0], n[1][1], n[1][2], 0.0],
+                [n[2][0], n[2][1], n[2][2], 0.0],
+            ],
+        }
+    }
+}
+
+// Must match `MeshInstance` in compute_shader.glsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GpuInstance {
+    pub world_to_local: [[f32; 4]; 4],
+    pub color: [f32; 3],
+    pub roughness: f32,
+    pub node_offset: i32,
+    pub triangle_offset: i32,
+    pub emission: f32,
+    pub _padding: i32,
+}
+
+pub struct GpuScene {
+    object_buffer: GLuint,
+    triangle_buffer: GLuint,
+    blas_buffer: GLuint,
+    instance_buffer: GLuint,
+    tlas_buffer: GLuint,
+    // (revision, deformation) of the uploaded meshes and their (node, triangle) offsets
+    uploaded_meshes: Vec<(u32, u32)>,
+    mesh_offsets: Vec<(i32, i32)>,
+}
+
+impl GpuScene {
+    pub fn new() -> Self {
+        let mut buffers = [0; 5];
+        unsafe {
+            gl::GenBuffers(buffers.len() as GLsizei, buffers.as_mut_ptr());
+        }
+        GpuScene {
+            object_buffer: buffers[0],
+            triangle_buffer: buffers[1],
+            blas_buffer: buffers[2],
+            instance_buffer: buffers[3],
+            tlas_buffer: buffers[4],
+            uploaded_meshes: Vec::new(),
+            mesh_offsets: Vec::new(),
+        }
+    }
+
+    // Uploads all objects followed by the particle spheres and binds the buffer
+    // for the compute shader. Returns the number of objects the shader should iterate over.
+    pub fn upload_objects(&self, objects: &[Object], particles: &[Object]) -> i32 {
+        let data: Vec<GpuObject> = objects.iter().chain(particles).map(GpuObject::from_object).collect();
+        let count = data.len() as i32;
+        upload_buffer(self.object_buffer, OBJECT_BUFFER_BINDING, data);
+        count
+    }
+
+    // Uploads triangles and BLAS nodes of all meshes when a mesh was added,
+    // removed or rebuilt. A mesh that was only refitted, like a moving cloth,
+    // keeps its layout and just its own range of both buffers is rewritten.
+    pub fn upload_meshes(&mut self, meshes: &[&Mesh]) {
+        let same_layout = self.uploaded_meshes.len() == meshes.len()
+            && self.uploaded_meshes.iter().zip(meshes.iter()).all(|((revision, _), mesh)| *revision == mesh.revision);
+        if same_layout {
+            for (m, mesh) in meshes.iter().enumerate() {
+                if self.uploaded_meshes[m].1 == mesh.deformation {
+                    continue;
+                }
+                let (node_offset, triangle_offset) = self.mesh_offsets[m];
+                let triangles: Vec<GpuTriangle> = mesh.triangles.iter().map(GpuTriangle::from_triangle).collect();
+                update_buffer(self.triangle_buffer, triangle_offset as usize, &triangles);
+                update_buffer(self.blas_buffer, node_offset as usize, &mesh.bvh.nodes);
+                self.uploaded_meshes[m].1 = mesh.deformation;
+            }
+            return;
+        }
+
+        let mut triangles = Vec::new();
+        let mut nodes: Vec<BvhNode> = Vec::new();
+        self.mesh_offsets.clear();
+        for mesh in meshes.iter() {
+            self.mesh_offsets.push((nodes.len() as i32, triangles.len() as i32));
+            nodes.extend_from_slice(&mesh.bvh.nodes);
+            triangles.extend(mesh.triangles.iter().map(GpuTriangle::from_triangle));
+        }
+        upload_buffer(self.triangle_buffer, TRIANGLE_BUFFER_BINDING, triangles);
+        upload_buffer(self.blas_buffer, BLAS_BUFFER_BINDING, nodes);
+        self.uploaded_meshes = meshes.iter().map(|mesh| (mesh.revision, mesh.deformation)).collect();
+    }
+
+    // Builds the TLAS over all instances and uploads it with the instances in
+    // leaf order. Returns the number of instances the shader should trace.
+    pub fn upload_instances(&self, meshes: &[&Mesh], instances: &[Instance]) -> i32 {
+        let valid: Vec<&Instance> = instances
+            .iter()
+            .filter(|instance| instance.mesh < meshes.len() && instance.mesh < self.mesh_offsets.len())
+            .collect();
+        let boxes: Vec<Aabb> = valid
+            .iter()
+            .map(|instance| meshes[instance.mesh].bounds.transformed(&instance.transform()))
+            .collect();
+        let tlas = Bvh::build(&boxes);
+
+        let data: Vec<GpuInstance> = tlas
+            .order
+            .iter()
+            .map(|&i| {
+                let instance = valid[i];
+                let (color, roughness, emission) = instance.material(meshes[instance.mesh]);
+                let (node_offset, triangle_offset) = self.mesh_offsets[instance.mesh];
+                GpuInstance {
+                    world_to_local: instance.inverse_transform(),
+                    color,
+                    roughness,
+                    node_offset,
+                    triangle_offset,
+                    emission,
+                    _padding: 0,
+                }
+            })
+            .collect();
+        upload_buffer(self.instance_buffer, INSTANCE_BUFFER_BINDING, data);
+        upload_buffer(self.tlas_buffer, TLAS_BUFFER_BINDING, tlas.nodes);
+        valid.len() as i32
+    }
+}
+
+// Replaces the contents of a shader storage buffer and binds it
+fn upload_buffer<T: Copy + Default>(buffer: GLuint, binding: GLuint, mut data: Vec<T>) {
+    // Zero sized buffers can't be bound, keep one unused entry around
+    if data.is_empty() {
+        data.push(T::default());
+    }
+    unsafe {
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, buffer);
+        gl::BufferData(
+            gl::SHADER_STORAGE_BUFFER,
+            (data.len() * std::mem::size_of::<T>()) as GLsizeiptr,
+            data.as_ptr() as *const _,
+            gl::DYNAMIC_DRAW,
+        );
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding, buffer);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+    }
+}
+
+// Overwrites part of a shader storage buffer starting at element `offset`, the buffer keeps its size and binding
+fn update_buffer<T: Copy>(buffer: GLuint, offset: usize, data: &[T]) {
+    if data.is_empty() {
+        return;
+    }
+    unsafe {
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, buffer);
+        gl::BufferSubData(
+            gl::SHADER_STORAGE_BUFFER,
+            (offset * std::mem::size_of::<T>()) as GLintptr,
+            std::mem::size_of_val(data) as GLsizeiptr,
+            data.as_ptr() as *const _,
+        );
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+    }
+}
@@ -28,11 +28,42 @@ mod window_manager;
 use window_manager::{window_manager::windows::{MainWindow, SandboxWindow}, *};
 mod readobj;
 use readobj::*;
+mod procedural;
+use procedural::*;
+mod scene;
+mod bvh;
+mod mesh;
+mod gpu_scene;
+mod sampler;
+mod physics;
+mod particles;
+use gpu_scene::GpuScene;
+use mesh::{Instance, Mesh};
+use physics::PhysicsWorld;
+use physics::math;
+use physics::query::Hit;
 
 
 
 fn main() {
+    // `--bench-physics [bodies] [steps]` times the physics world without opening a window,
+    // without a body count it times 4000 and 8000 bodies
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|arg| arg.as_str()) == Some("--bench-physics") {
+        let steps = args.get(3).and_then(|n| n.parse().ok()).unwrap_or(600);
+        match args.get(2).and_then(|n| n.parse().ok()) {
+            Some(body_count) => physics::bench::run(body_count, steps),
+            None => {
+                for body_count in physics::bench::DEFAULT_BODY_COUNTS {
+                    physics::bench::run(body_count, steps);
+                }
+            }
+        }
+        return;
+    }
+
     let mut is_accumulate=0;
+    let mut frame_number: i32 = 0;
     let mut SCREEN_WIDTH = 1280;
     let mut SCREEN_HEIGHT = 700;
     let my_position = glm::vec3(0.0, 0.0, 3.0);
@@ -40,6 +71,8 @@ fn main() {
     let my_yaw = -90.0;
     let my_pitch = 0.0;
     let mut moveCamera=false;
+    // Object grabbed by a left click and the camera position at the time, flung on release
+    let mut fling_start: Option<(Hit, [f32; 3])> = None;
     let mut my_camera = Camera::new(my_position, my_up, my_yaw, my_pitch,45.0);
 
     let sdl_context = sdl2::init().unwrap();
@@ -99,8 +132,14 @@ fn main() {
     let compute_shader_program = link_program(compute_shader, 0);
     let quad_shader_program = link_program(quad_vertex_shader, quad_fragment_shader);
 
-    // Create a texture for the compute shader to write to
-    let mut texture = create_texture(SCREEN_WIDTH,SCREEN_HEIGHT);
+    // Object buffer read by the compute shader
+    let mut gpu_scene = GpuScene::new();
+
+    // Create a texture for the compute shader to write to, sized by the render resolution
+    let mut render_size = (SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut texture = create_texture(render_size.0, render_size.1, 0);
+    // Per-pixel luminance statistics for adaptive sampling
+    let mut variance_texture = create_texture(render_size.0, render_size.1, 2);
 
     // Set up a fullscreen quad
     let vertices: [f32; 8] = [
@@ -135,14 +174,33 @@ fn main() {
     
     let now: Instant = Instant::now();
     let delta_time: f32 = now.duration_since(last_frame_time).as_secs_f32();
+    let mut physics_world = PhysicsWorld::new();
+    let mut last_physics_time = Instant::now();
     
     'running: loop {
+        frame_number = frame_number.wrapping_add(1);
         let timernow: Instant = Instant::now();
         let timer: f32 = timernow.duration_since(last_frame_time).as_secs_f32();
         egui_state.input.time = Some(start_time.elapsed().as_secs_f64());
-        let mut objects_clone= main_window.sandbox_window.Objects.clone();
-        for object in main_window.sandbox_window.Objects.iter_mut() {
-            object.update(delta_time, &mut objects_clone);
+        let physics_time = Instant::now();
+        let physics_delta = physics_time.duration_since(last_physics_time).as_secs_f32();
+        last_physics_time = physics_time;
+        physics_world.settings = main_window.sandbox_window.physics_settings;
+        physics_world.fields.clone_from(&main_window.sandbox_window.force_fields);
+        let sandbox = &mut *main_window.sandbox_window;
+        // Particles and cloths run, freeze and reset with the simulation
+        if sandbox.simulation.run(&mut physics_world, physics_delta, &mut sandbox.Objects, &mut sandbox.joints) {
+            sandbox.particles.clear();
+            for cloth in sandbox.cloths.iter_mut() {
+                cloth.reset();
+            }
+        }
+        if sandbox.simulation.is_playing {
+            let particle_delta = physics_delta.min(0.25) * sandbox.simulation.time_scale;
+            sandbox.particles.update(&sandbox.emitters, sandbox.physics_settings.gravity, particle_delta);
+            for cloth in sandbox.cloths.iter_mut() {
+                cloth.update(particle_delta, &sandbox.Objects, &sandbox.physics_settings);
+            }
         }
 
         egui_ctx.begin_frame(egui_state.input.take());
@@ -180,26 +238,31 @@ let camera_front = my_camera.front;
 let camera_up = my_camera.up;
 let camera_right = my_camera.right;
 let camera_fov = my_camera.fov;
-let objects_position = main_window.sandbox_window.Objects.clone();
-let object_color = objects_position.iter().map(|s| s.color).collect::<Vec<_>>();
-let object_radius = objects_position.iter().map(|s| s.radius).collect::<Vec<_>>();
-let object_roughness = objects_position.iter().map(|s| s.roughness).collect::<Vec<_>>();
-let object_emission = objects_position.iter().map(|s| s.emission).collect::<Vec<_>>();
-let object_is_cube = objects_position.iter().map(|s| s.is_cube).collect::<Vec<_>>();
-let object_is_glass = objects_position.iter().map(|s| s.is_glass).collect::<Vec<_>>();
-let object_reflectness = objects_position.iter().map(|s| s.reflectness).collect::<Vec<_>>();
-// let (vertices, normals, triangles) = match read_obj_file("triangle.obj") {
-//     Ok((vertices, normals, triangles)) => (vertices, normals, triangles),
-//     Err(e) => {
-//         eprintln!("Error reading OBJ file: {}", e);
-//         return;
-//     }
-// };
-
-// let (vertex_data, normal_data, index_data) = prepare_mesh_data(&triangles);
-
-
-let object_size = objects_position.iter().map(|s| s.size).collect::<Vec<_>>();
+let particle_spheres = main_window.sandbox_window.particles.spheres(&main_window.sandbox_window.emitters);
+let num_objects = gpu_scene.upload_objects(&main_window.sandbox_window.Objects, &particle_spheres);
+// Cloths are drawn as extra meshes after the scene ones, each with one instance
+let sandbox = &main_window.sandbox_window;
+let meshes: Vec<&Mesh> = sandbox.meshes.iter().chain(sandbox.cloths.iter().map(|cloth| &cloth.mesh)).collect();
+let instances: Vec<Instance> = sandbox
+    .instances
+    .iter()
+    .cloned()
+    .chain(sandbox.cloths.iter().enumerate().map(|(c, cloth)| cloth.instance(sandbox.meshes.len() + c)))
+    .collect();
+gpu_scene.upload_meshes(&meshes);
+let num_instances = gpu_scene.upload_instances(&meshes, &instances);
+let render_settings = main_window.sandbox_window.render_settings;
+let new_render_size = render_settings.resolution(SCREEN_WIDTH, SCREEN_HEIGHT);
+if new_render_size != render_size {
+    render_size = new_render_size;
+    unsafe {
+        gl::DeleteTextures(1, &texture);
+        gl::DeleteTextures(1, &variance_texture);
+    }
+    texture = create_texture(render_size.0, render_size.1, 0);
+    variance_texture = create_texture(render_size.0, render_size.1, 2);
+}
+let (texture_nodes, texture_ranges) = flatten_graphs(&main_window.sandbox_window.texture_graphs);
 let skycolor = main_window.sandbox_window.skycolor;
 let is_fisheye = main_window.sandbox_window.is_fisheye;
 
@@ -216,54 +279,80 @@ unsafe {
     gl::Uniform3f(camera_right_loc, camera_right[0], camera_right[1], camera_right[2]);
     let camera_fov_loc = gl::GetUniformLocation(compute_shader_program, CString::new("fov").unwrap().as_ptr());
     gl::Uniform1f(camera_fov_loc, camera_fov);
-    let object_pos_loc = gl::GetUniformLocation(compute_shader_program, CString::new("objects_position").unwrap().as_ptr());
-    let object_color_loc = gl::GetUniformLocation(compute_shader_program, CString::new("objects_color").unwrap().as_ptr());
-    let object_radius_loc = gl::GetUniformLocation(compute_shader_program, CString::new("objects_radius").unwrap().as_ptr());
-    let roughness_loc = gl::GetUniformLocation(compute_shader_program, CString::new("objects_roughness").unwrap().as_ptr());
-    let emission_loc = gl::GetUniformLocation(compute_shader_program, CString::new("objects_emission").unwrap().as_ptr());
-    let is_cube_loc = gl::GetUniformLocation(compute_shader_program, CString::new("is_cube").unwrap().as_ptr());
-    let objects_size_loc = gl::GetUniformLocation(compute_shader_program, CString::new("objects_size").unwrap().as_ptr());
+    let num_objects_loc = gl::GetUniformLocation(compute_shader_program, CString::new("num_objects").unwrap().as_ptr());
+    let num_instances_loc = gl::GetUniformLocation(compute_shader_program, CString::new("num_instances").unwrap().as_ptr());
 
     let time_loc = gl::GetUniformLocation(compute_shader_program, CString::new("currentTime").unwrap().as_ptr());
     let accumulation_loc = gl::GetUniformLocation(compute_shader_program, CString::new("is_accumulation").unwrap().as_ptr());
     let is_fisheye_loc = gl::GetUniformLocation(compute_shader_program, CString::new("is_fisheye").unwrap().as_ptr());
     let skycolor_loc = gl::GetUniformLocation(compute_shader_program, CString::new("skycolor").unwrap().as_ptr());
     let camera_vel_loc = gl::GetUniformLocation(compute_shader_program, CString::new("camera_velocity").unwrap().as_ptr());
-    let object_isglass_loc = gl::GetUniformLocation(compute_shader_program, CString::new("is_glass").unwrap().as_ptr());
-    let object_reflectness_loc = gl::GetUniformLocation(compute_shader_program, CString::new("is_glass").unwrap().as_ptr());
-
-    // Mesh
-    // let vertices_loc = gl::GetUniformLocation(compute_shader_program, CString::new("vertices").unwrap().as_ptr());
-    // let normals_loc = gl::GetUniformLocation(compute_shader_program, CString::new("normals").unwrap().as_ptr());
-    // let triangles_loc = gl::GetUniformLocation(compute_shader_program, CString::new("triangles").unwrap().as_ptr());
+    let is_spectral_loc = gl::GetUniformLocation(compute_shader_program, CString::new("is_spectral").unwrap().as_ptr());
+
+    // Render settings
+    let max_bounces_loc = gl::GetUniformLocation(compute_shader_program, CString::new("max_bounces").unwrap().as_ptr());
+    let samples_per_pixel_loc = gl::GetUniformLocation(compute_shader_program, CString::new("samples_per_pixel").unwrap().as_ptr());
+    let rr_min_depth_loc = gl::GetUniformLocation(compute_shader_program, CString::new("rr_min_depth").unwrap().as_ptr());
+    let low_discrepancy_loc = gl::GetUniformLocation(compute_shader_program, CString::new("is_low_discrepancy").unwrap().as_ptr());
+    let frame_number_loc = gl::GetUniformLocation(compute_shader_program, CString::new("frameNumber").unwrap().as_ptr());
+    let shutter_open_loc = gl::GetUniformLocation(compute_shader_program, CString::new("shutter_open").unwrap().as_ptr());
+    let shutter_close_loc = gl::GetUniformLocation(compute_shader_program, CString::new("shutter_close").unwrap().as_ptr());
+    let adaptive_loc = gl::GetUniformLocation(compute_shader_program, CString::new("is_adaptive").unwrap().as_ptr());
+    let adaptive_threshold_loc = gl::GetUniformLocation(compute_shader_program, CString::new("adaptive_threshold").unwrap().as_ptr());
+    let adaptive_min_samples_loc = gl::GetUniformLocation(compute_shader_program, CString::new("adaptive_min_samples").unwrap().as_ptr());
+    let adaptive_max_factor_loc = gl::GetUniformLocation(compute_shader_program, CString::new("adaptive_max_factor").unwrap().as_ptr());
+
+    // Fog
+    let fog_density_loc = gl::GetUniformLocation(compute_shader_program, CString::new("fog_density").unwrap().as_ptr());
+    let fog_albedo_loc = gl::GetUniformLocation(compute_shader_program, CString::new("fog_albedo").unwrap().as_ptr());
+    let fog_anisotropy_loc = gl::GetUniformLocation(compute_shader_program, CString::new("fog_anisotropy").unwrap().as_ptr());
+    let fog_extent_loc = gl::GetUniformLocation(compute_shader_program, CString::new("fog_extent").unwrap().as_ptr());
+
+    // Procedural textures
+    let num_texture_graphs_loc = gl::GetUniformLocation(compute_shader_program, CString::new("num_texture_graphs").unwrap().as_ptr());
+    let texture_graph_range_loc = gl::GetUniformLocation(compute_shader_program, CString::new("texture_graph_range").unwrap().as_ptr());
+    let texture_node_kind_loc = gl::GetUniformLocation(compute_shader_program, CString::new("texture_node_kind").unwrap().as_ptr());
+    let texture_node_params_loc = gl::GetUniformLocation(compute_shader_program, CString::new("texture_node_params").unwrap().as_ptr());
+    let texture_node_color_a_loc = gl::GetUniformLocation(compute_shader_program, CString::new("texture_node_color_a").unwrap().as_ptr());
+    let texture_node_color_b_loc = gl::GetUniformLocation(compute_shader_program, CString::new("texture_node_color_b").unwrap().as_ptr());
 
     gl::Uniform1f(time_loc as GLint, timer);
     gl::Uniform1i(is_fisheye_loc as GLint, is_fisheye as i32);
+    gl::Uniform1i(is_spectral_loc, render_settings.is_spectral as i32);
+    gl::Uniform1i(max_bounces_loc, render_settings.max_bounces);
+    gl::Uniform1i(samples_per_pixel_loc, render_settings.samples_per_pixel);
+    gl::Uniform1i(rr_min_depth_loc, render_settings.rr_min_depth);
+    gl::Uniform1i(low_discrepancy_loc, render_settings.is_low_discrepancy as i32);
+    gl::Uniform1i(frame_number_loc, frame_number);
+    gl::Uniform1f(shutter_open_loc, render_settings.shutter_open);
+    gl::Uniform1f(shutter_close_loc, render_settings.shutter_close);
+    gl::Uniform1i(adaptive_loc, render_settings.is_adaptive as i32);
+    gl::Uniform1f(adaptive_threshold_loc, render_settings.adaptive_threshold);
+    gl::Uniform1i(adaptive_min_samples_loc, render_settings.adaptive_min_samples);
+    gl::Uniform1i(adaptive_max_factor_loc, render_settings.adaptive_max_factor);
+    gl::Uniform1i(num_objects_loc, num_objects);
+    gl::Uniform1i(num_instances_loc, num_instances);
     gl::Uniform1i(accumulation_loc as GLint, is_accumulate as i32);
     gl::Uniform3f(skycolor_loc as GLint, skycolor[0] as f32 / 255.0, skycolor[1] as f32 / 255.0, skycolor[2] as f32 / 255.0);
     gl::Uniform3f(camera_vel_loc as GLint, my_camera.velocity.x, my_camera.velocity.y, my_camera.velocity.z);
-    for i in 0..objects_position.len() {
-            gl::Uniform3f(objects_size_loc +i as GLint, object_size[i][0],object_size[i][1],object_size[i][2]);
-            gl::Uniform1i(is_cube_loc +i as GLint, object_is_cube[i] as i32);
-            gl::Uniform3f(object_pos_loc + i as GLint, objects_position[i].position[0], objects_position[i].position[1], objects_position[i].position[2]);
-            gl::Uniform3f(object_color_loc + i as GLint, object_color[i][0], object_color[i][1], object_color[i][2]);
-            gl::Uniform1f(object_radius_loc + i as GLint, object_radius[i]);
-            gl::Uniform1f(roughness_loc + i as GLint, object_roughness[i]);
-            gl::Uniform1f(object_reflectness_loc + i as GLint, object_reflectness[i]);
-            gl::Uniform1f(emission_loc + i as GLint, object_emission[i]);
-
-            gl::Uniform1i(object_isglass_loc +i as GLint, object_is_glass[i] as i32);
-
+    gl::Uniform1f(fog_density_loc, main_window.sandbox_window.fog_density);
+    let fog_albedo = main_window.sandbox_window.fog_albedo;
+    gl::Uniform3f(fog_albedo_loc, fog_albedo[0], fog_albedo[1], fog_albedo[2]);
+    gl::Uniform1f(fog_anisotropy_loc, main_window.sandbox_window.fog_anisotropy);
+    gl::Uniform1f(fog_extent_loc, main_window.sandbox_window.fog_extent);
+    gl::Uniform1i(num_texture_graphs_loc, texture_ranges.len() as i32);
+    for i in 0..texture_ranges.len() {
+            gl::Uniform2i(texture_graph_range_loc + i as GLint, texture_ranges[i][0], texture_ranges[i][1]);
+    }
+    for i in 0..texture_nodes.len() {
+            let node = &texture_nodes[i];
+            gl::Uniform1i(texture_node_kind_loc + i as GLint, node.kind.id());
+            gl::Uniform4f(texture_node_params_loc + i as GLint, node.scale, node.param, node.input_a as f32, node.input_b as f32);
+            gl::Uniform3f(texture_node_color_a_loc + i as GLint, node.color_a[0], node.color_a[1], node.color_a[2]);
+            gl::Uniform3f(texture_node_color_b_loc + i as GLint, node.color_b[0], node.color_b[1], node.color_b[2]);
     }
 
-        // Upload mesh data (vertices, normals, triangles)
-    // // Send mesh data to the shader
-    // gl::Uniform3fv(vertices_loc, vertex_data.len() as i32 / 3, vertex_data.as_ptr());
-    // gl::Uniform3fv(normals_loc, normal_data.len() as i32 / 3, normal_data.as_ptr());
-    // gl::Uniform1iv(triangles_loc, index_data.len() as i32, index_data.as_ptr());
-
-    gl::DispatchCompute(SCREEN_WIDTH / 8, SCREEN_HEIGHT / 8, 1);
-    gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    dispatch_render(compute_shader_program, render_size.0, render_size.1, render_settings.tile_size);
 }
 
         //////
@@ -301,7 +390,7 @@ for event in event_pump.poll_iter() {
             unsafe {
                 gl::Viewport(0,0,SCREEN_WIDTH as i32,SCREEN_HEIGHT as i32);
             };
-            texture = unsafe { create_texture(SCREEN_WIDTH, SCREEN_HEIGHT)}
+            // Render textures follow on the next frame, see render_settings.resolution
         }
         Event::KeyDown { keycode: Some(Keycode::W), .. } => {
             my_camera.process_keyboard(CameraMovement::Forward, delta_time);
@@ -342,6 +431,13 @@ for event in event_pump.poll_iter() {
              match mouse_btn {
                 sdl2::mouse::MouseButton::Left => {
                     egui_state.process_input(&window, event, &mut painter);
+                    // Clicks outside the UI select the object under the cursor
+                    if !egui_ctx.is_pointer_over_area() {
+                        let aspect_ratio = render_size.0 as f32 / render_size.1.max(1) as f32;
+                        let direction = my_camera.screen_ray(x as f32, y as f32, SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32, aspect_ratio, main_window.sandbox_window.is_fisheye);
+                        let origin = [my_camera.position.x, my_camera.position.y, my_camera.position.z];
+                        fling_start = main_window.sandbox_window.select_at(&mut physics_world, origin, [direction.x, direction.y, direction.z]).map(|hit| (hit, origin));
+                    }
                 }
                 sdl2::mouse::MouseButton::Right => {
                     moveCamera = true;
@@ -354,6 +450,16 @@ for event in event_pump.poll_iter() {
              match mouse_btn {
                 sdl2::mouse::MouseButton::Left => {
                     egui_state.process_input(&window, event, &mut painter);
+                    // Dragging from a grabbed object throws it towards the release point,
+                    // kept at the depth it was grabbed at
+                    if let Some((hit, origin)) = fling_start.take() {
+                        let aspect_ratio = render_size.0 as f32 / render_size.1.max(1) as f32;
+                        let direction = my_camera.screen_ray(x as f32, y as f32, SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32, aspect_ratio, main_window.sandbox_window.is_fisheye);
+                        let target = math::add(origin, math::scale([direction.x, direction.y, direction.z], hit.distance));
+                        if math::length(math::sub(target, hit.point)) > 0.01 {
+                            physics_world.fling(&mut main_window.sandbox_window.Objects, hit.object, hit.point, target);
+                        }
+                    }
                 }
                 sdl2::mouse::MouseButton::Right => {
                     moveCamera = false;
@@ -389,15 +495,25 @@ for event in event_pump.poll_iter() {
         // Use the compute shader program to process the texture
         unsafe {
             gl::UseProgram(compute_shader_program);
-            gl::DispatchCompute(SCREEN_WIDTH / 8, SCREEN_HEIGHT / 8, 1);
-            gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+            dispatch_render(compute_shader_program, render_size.0, render_size.1, render_settings.tile_size);
         }
 
         // Render the texture to the screen
         unsafe {
             gl::Clear(gl::COLOR_BUFFER_BIT);
             gl::UseProgram(quad_shader_program);
+            let screen_texture_loc = gl::GetUniformLocation(quad_shader_program, CString::new("screen_texture").unwrap().as_ptr());
+            let variance_texture_loc = gl::GetUniformLocation(quad_shader_program, CString::new("variance_texture").unwrap().as_ptr());
+            let display_mode_loc = gl::GetUniformLocation(quad_shader_program, CString::new("display_mode").unwrap().as_ptr());
+            let quad_threshold_loc = gl::GetUniformLocation(quad_shader_program, CString::new("adaptive_threshold").unwrap().as_ptr());
+            gl::Uniform1i(screen_texture_loc, 0);
+            gl::Uniform1i(variance_texture_loc, 1);
+            gl::Uniform1i(display_mode_loc, render_settings.display_mode.id());
+            gl::Uniform1f(quad_threshold_loc, render_settings.adaptive_threshold);
             gl::BindVertexArray(vao);
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_2D, variance_texture);
+            gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, texture);
             gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
         }
@@ -411,7 +527,35 @@ for event in event_pump.poll_iter() {
 }
 
 
-fn create_texture(width: u32, height: u32) -> GLuint {
+// Runs the compute shader over a width x height image. With a tile size the
+// image is split into separate dispatches so one heavy frame can't trip the
+// GPU watchdog.
+unsafe fn dispatch_render(program: GLuint, width: u32, height: u32, tile_size: u32) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    let tile_offset_loc = gl::GetUniformLocation(program, CString::new("tile_offset").unwrap().as_ptr());
+    let tile_end_loc = gl::GetUniformLocation(program, CString::new("tile_end").unwrap().as_ptr());
+    let (tile_width, tile_height) = if tile_size == 0 { (width, height) } else { (tile_size, tile_size) };
+    for y in (0..height).step_by(tile_height as usize) {
+        for x in (0..width).step_by(tile_width as usize) {
+            let (extent_x, extent_y) = (tile_width.min(width - x), tile_height.min(height - y));
+            gl::Uniform2i(tile_offset_loc, x as i32, y as i32);
+            gl::Uniform2i(tile_end_loc, (x + extent_x) as i32, (y + extent_y) as i32);
+            // Round up, the shader skips invocations outside the tile
+            let groups_x = (extent_x + 7) / 8;
+            let groups_y = (extent_y + 7) / 8;
+            gl::DispatchCompute(groups_x, groups_y, 1);
+            if tile_size != 0 {
+                gl::Flush();
+            }
+        }
+    }
+    gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+}
+
+// Creates a float texture and binds it to image `unit` of the compute shader
+fn create_texture(width: u32, height: u32, unit: GLuint) -> GLuint {
     let mut texture = 0;
     unsafe {
         gl::GenTextures(1, &mut texture);
@@ -429,7 +573,9 @@ fn create_texture(width: u32, height: u32) -> GLuint {
         );
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-        gl::BindImageTexture(0, texture, 0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RGBA32F);
+        // Start from zero so accumulation doesn't read garbage sample counts
+        gl::ClearTexImage(texture, 0, gl::RGBA, gl::FLOAT, std::ptr::null());
+        gl::BindImageTexture(unit, texture, 0, gl::FALSE, 0, gl::READ_WRITE, gl::RGBA32F);
     }
     texture
 }
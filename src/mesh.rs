@@ -0,0 +1,309 @@
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::bvh::{Aabb, Bvh};
+use crate::readobj::{prepare_mesh_data, read_obj_file};
+use crate::sampler::pcg_hash;
+
+// Built in meshes, usable as a mesh source in place of an OBJ path
+pub const BUILTIN_MESHES: [&str; 2] = ["cube", "sphere"];
+
+static NEXT_REVISION: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshTriangle {
+    pub vertices: [[f32; 3]; 3],
+    pub normals: [[f32; 3]; 3],
+}
+
+// Geometry shared by all instances of it, triangles are stored in BLAS leaf order
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub source: String,
+    pub triangles: Vec<MeshTriangle>,
+    pub bvh: Bvh,
+    pub bounds: Aabb,
+    // Unique to every built geometry, the GPU copy is refreshed when it changes
+    pub revision: u32,
+    // Counts the refits of the geometry, its GPU copy is rewritten in place when it changes
+    pub deformation: u32,
+    // Material used by instances that don't override it
+    pub color: [f32; 3],
+    pub roughness: f32,
+    pub emission: f32,
+}
+
+impl Mesh {
+    // Loads a built in mesh by name or an OBJ file by path
+    pub fn load(source: &str) -> Result<Mesh, io::Error> {
+        let triangles = match source {
+            "cube" => cube_triangles(),
+            "sphere" => sphere_triangles(16, 32),
+            path => {
+                let (_, _, triangles) = read_obj_file(path)?;
+                let (vertex_data, normal_data, _) = prepare_mesh_data(&triangles);
+                vertex_data
+                    .chunks(9)
+                    .zip(normal_data.chunks(9))
+                    .map(|(v, n)| MeshTriangle {
+                        vertices: [[v[0], v[1], v[2]], [v[3], v[4], v[5]], [v[6], v[7], v[8]]],
+                        normals: [[n[0], n[1], n[2]], [n[3], n[4], n[5]], [n[6], n[7], n[8]]],
+                    })
+                    .collect()
+            }
+        };
+        if triangles.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Mesh has no triangles: {}", source)));
+        }
+        Ok(Mesh::from_triangles(source, triangles))
+    }
+
+    pub fn from_triangles(source: &str, mut triangles: Vec<MeshTriangle>) -> Mesh {
+        // OBJ files without normals get flat shading
+        for triangle in triangles.iter_mut() {
+            let face_normal = face_normal(&triangle.vertices);
+            for normal in triangle.normals.iter_mut() {
+                if dot(*normal, *normal) == 0.0 {
+                    *normal = face_normal;
+                }
+            }
+        }
+
+        let boxes: Vec<Aabb> = triangles.iter().map(|t| Aabb::from_points(&t.vertices)).collect();
+        let bvh = Bvh::build(&boxes);
+        let triangles: Vec<MeshTriangle> = bvh.order.iter().map(|&i| triangles[i]).collect();
+        let bounds = boxes.iter().fold(Aabb::empty(), |bounds, b| bounds.union(b));
+        Mesh {
+            source: source.to_string(),
+            triangles,
+            bvh,
+            bounds,
+            revision: NEXT_REVISION.fetch_add(1, Ordering::Relaxed),
+            deformation: 0,
+            color: [180.0; 3],
+            roughness: 1.0,
+            emission: 0.0,
+        }
+    }
+
+    // Fits the BLAS around the triangles after their vertices were rewritten in
+    // place. The hierarchy and the triangle order stay, so the mesh keeps its
+    // layout on the GPU. Children always come after their parent.
+    pub fn refit(&mut self) {
+        for n in (0..self.bvh.nodes.len()).rev() {
+            let node = self.bvh.nodes[n];
+            let first = node.left_first as usize;
+            let bounds = if node.count > 0 {
+                self.triangles[first..first + node.count as usize]
+                    .iter()
+                    .fold(Aabb::empty(), |bounds, t| bounds.union(&Aabb::from_points(&t.vertices)))
+            } else {
+                let (left, right) = (self.bvh.nodes[first], self.bvh.nodes[first + 1]);
+                Aabb { min: left.min, max: left.max }.union(&Aabb { min: right.min, max: right.max })
+            };
+            self.bvh.nodes[n].min = bounds.min;
+            self.bvh.nodes[n].max = bounds.max;
+        }
+        self.bounds = self.bvh.nodes.first().map_or(Aabb::empty(), |root| Aabb { min: root.min, max: root.max });
+        self.deformation = self.deformation.wrapping_add(1);
+    }
+}
+
+// A placement of a mesh with its own transform and optional material
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instance {
+    pub mesh: usize,
+    pub position: [f32; 3],
+    pub rotation: [f32; 3], // Euler angles in degrees, applied X, then Y, then Z
+    pub scale: f32,
+    pub override_material: bool,
+    pub color: [f32; 3],
+    pub roughness: f32,
+    pub emission: f32,
+}
+
+impl Instance {
+    pub fn new(mesh: usize, position: [f32; 3]) -> Self {
+        Instance {
+            mesh,
+            position,
+            rotation: [0.0; 3],
+            scale: 1.0,
+            override_material: false,
+            color: [120.0; 3],
+            roughness: 1.0,
+            emission: 0.0,
+        }
+    }
+
+    // Column major local to world matrix
+    pub fn transform(&self) -> [[f32; 4]; 4] {
+        let r = rotation_matrix(self.rotation);
+        let s = self.scale;
+        [
+            [r[0][0] * s, r[1][0] * s, r[2][0] * s, 0.0],
+            [r[0][1] * s, r[1][1] * s, r[2][1] * s, 0.0],
+            [r[0][2] * s, r[1][2] * s, r[2][2] * s, 0.0],
+            [self.position[0], self.position[1], self.position[2], 1.0],
+        ]
+    }
+
+    // Column major world to local matrix
+    pub fn inverse_transform(&self) -> [[f32; 4]; 4] {
+        let r = rotation_matrix(self.rotation);
+        let inv_s = 1.0 / self.scale.max(1e-6);
+        let p = self.position;
+        let mut translation = [0.0; 3];
+        for row in 0..3 {
+            translation[row] = -(r[0][row] * p[0] + r[1][row] * p[1] + r[2][row] * p[2]) * inv_s;
+        }
+        [
+            [r[0][0] * inv_s, r[0][1] * inv_s, r[0][2] * inv_s, 0.0],
+            [r[1][0] * inv_s, r[1][1] * inv_s, r[1][2] * inv_s, 0.0],
+            [r[2][0] * inv_s, r[2][1] * inv_s, r[2][2] * inv_s, 0.0],
+            [translation[0], translation[1], translation[2], 1.0],
+        ]
+    }
+
+    // Material after applying the override
+    pub fn material(&self, mesh: &Mesh) -> ([f32; 3], f32, f32) {
+        if self.override_material {
+            (self.color, self.roughness, self.emission)
+        } else {
+            (mesh.color, mesh.roughness, mesh.emission)
+        }
+    }
+}
+
+// Row major rotation Rz * Ry * Rx from Euler angles in degrees
+fn rotation_matrix(degrees: [f32; 3]) -> [[f32; 3]; 3] {
+    let (sx, cx) = degrees[0].to_radians().sin_cos();
+    let (sy, cy) = degrees[1].to_radians().sin_cos();
+    let (sz, cz) = degrees[2].to_radians().sin_cos();
+    [
+        [cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx],
+        [sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx],
+        [-sy, cy * sx, cy * cx],
+    ]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn face_normal(v: &[[f32; 3]; 3]) -> [f32; 3] {
+    let e1 = [v[1][0] - v[0][0], v[1][1] - v[0][1], v[1][2] - v[0][2]];
+    let e2 = [v[2][0] - v[0][0], v[2][1] - v[0][1], v[2][2] - v[0][2]];
+    let n = [
+        e1[1] * e2[2] - e1[2] * e2[1],
+        e1[2] * e2[0] - e1[0] * e2[2],
+        e1[0] * e2[1] - e1[1] * e2[0],
+    ];
+    let length = dot(n, n).sqrt();
+    if length > 0.0 {
+        [n[0] / length, n[1] / length, n[2] / length]
+    } else {
+        [0.0, 1.0, 0.0]
+    }
+}
+
+// Unit cube centered on the origin
+fn cube_triangles() -> Vec<MeshTriangle> {
+    let mut triangles = Vec::with_capacity(12);
+    for axis in 0..3 {
+        for &side in [-0.5f32, 0.5].iter() {
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+            let corner = |a: f32, b: f32| {
+                let mut p = [0.0; 3];
+                p[axis] = side;
+                p[u] = a;
+                p[v] = b;
+                p
+            };
+            let mut normal = [0.0; 3];
+            normal[axis] = side.signum();
+            let quad = [corner(-0.5, -0.5), corner(0.5, -0.5), corner(0.5, 0.5), corner(-0.5, 0.5)];
+            // Keep counter clockwise winding seen from outside
+            let (b, c) = if side > 0.0 { (1, 2) } else { (2, 1) };
+            triangles.push(MeshTriangle { vertices: [quad[0], quad[b], quad[c]], normals: [normal; 3] });
+            let (b, c) = if side > 0.0 { (2, 3) } else { (3, 2) };
+            triangles.push(MeshTriangle { vertices: [quad[0], quad[b], quad[c]], normals: [normal; 3] });
+        }
+    }
+    triangles
+}
+
+// UV sphere of radius 1 with smooth normals
+fn sphere_triangles(rings: usize, segments: usize) -> Vec<MeshTriangle> {
+    let point = |ring: usize, segment: usize| {
+        let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+        let phi = 2.0 * std::f32::consts::PI * segment as f32 / segments as f32;
+        [theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()]
+    };
+    let mut triangles = Vec::with_capacity(rings * segments * 2);
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let p00 = point(ring, segment);
+            let p01 = point(ring, segment + 1);
+            let p10 = point(ring + 1, segment);
+            let p11 = point(ring + 1, segment + 1);
+            if ring > 0 {
+                triangles.push(MeshTriangle { vertices: [p00, p01, p10], normals: [p00, p01, p10] });
+            }
+            if ring + 1 < rings {
+                triangles.push(MeshTriangle { vertices: [p01, p11, p10], normals: [p01, p11, p10] });
+            }
+        }
+    }
+    triangles
+}
+
+// Grid of counts[0] x counts[1] x counts[2] instances starting at origin
+pub fn instance_grid(mesh: usize, origin: [f32; 3], counts: [u32; 3], spacing: [f32; 3]) -> Vec<Instance> {
+    let mut instances = Vec::new();
+    for x in 0..counts[0] {
+        for y in 0..counts[1] {
+            for z in 0..counts[2] {
+                let position = [
+                    origin[0] + x as f32 * spacing[0],
+                    origin[1] + y as f32 * spacing[1],
+                    origin[2] + z as f32 * spacing[2],
+                ];
+                instances.push(Instance::new(mesh, position));
+            }
+        }
+    }
+    instances
+}
+
+// Random placements inside a box of half size `extent` around center
+pub fn instance_scatter(
+    mesh: usize,
+    center: [f32; 3],
+    extent: [f32; 3],
+    count: u32,
+    scale_range: [f32; 2],
+    random_rotation: bool,
+    seed: u32,
+) -> Vec<Instance> {
+    let mut state = pcg_hash(seed);
+    let mut random = move || {
+        state = pcg_hash(state);
+        (state >> 8) as f32 / (1u32 << 24) as f32
+    };
+    (0..count)
+        .map(|_| {
+            let mut position = [0.0; 3];
+            for axis in 0..3 {
+                position[axis] = center[axis] + (random() * 2.0 - 1.0) * extent[axis];
+            }
+            let mut instance = Instance::new(mesh, position);
+            instance.scale = scale_range[0] + (scale_range[1] - scale_range[0]) * random();
+            if random_rotation {
+                instance.rotation = [random() * 360.0, random() * 360.0, random() * 360.0];
+            }
+            instance
+        })
+        .collect()
+}
@@ -0,0 +1,290 @@
+use egui::{Slider, Ui};
+
+use crate::object::Object;
+use crate::physics::math::{add, cross, normalize, scale};
+use crate::sampler::pcg_hash;
+
+// Sparks, dust and debris. Particles are simulated on the CPU and never
+// collide, each frame they are handed to the ray tracer as tiny spheres
+// appended to the objects, so emissive ones light the scene like any other
+// emitter and their velocity gives them motion blur.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParticleEmitter {
+    pub is_enabled: bool,
+    pub position: [f32; 3],
+    // Axis of the cone particles leave in
+    pub direction: [f32; 3],
+    // Half angle of the cone in degrees
+    pub spread: f32,
+    // Launch speed range
+    pub speed: [f32; 2],
+    // Particles per second
+    pub rate: f32,
+    // Seconds a particle lives
+    pub lifetime: f32,
+    // Fraction of the scene gravity particles fall with
+    pub gravity_scale: f32,
+    // Fraction of the velocity lost per second
+    pub drag: f32,
+    // Appearance at birth and at death, interpolated over the life
+    pub start_color: [f32; 3],
+    pub end_color: [f32; 3],
+    pub start_size: f32,
+    pub end_size: f32,
+    pub start_emission: f32,
+    pub end_emission: f32,
+    // Live particles are capped, spawning waits for old ones to die
+    pub max_particles: u32,
+}
+
+impl ParticleEmitter {
+    // Fast, short lived glowing sparks that fall and cool down
+    pub fn sparks() -> Self {
+        ParticleEmitter {
+            is_enabled: true,
+            position: [0.0, 1.0, 0.0],
+            direction: [0.0, 1.0, 0.0],
+            spread: 35.0,
+            speed: [4.0, 8.0],
+            rate: 60.0,
+            lifetime: 1.2,
+            gravity_scale: 1.0,
+            drag: 0.3,
+            start_color: [255.0, 200.0, 80.0],
+            end_color: [255.0, 60.0, 10.0],
+            start_size: 0.03,
+            end_size: 0.01,
+            start_emission: 20.0,
+            end_emission: 0.0,
+            max_particles: 256,
+        }
+    }
+
+    // Slow puffs that drift up and grow
+    pub fn dust() -> Self {
+        ParticleEmitter {
+            is_enabled: true,
+            position: [0.0, 0.2, 0.0],
+            direction: [0.0, 1.0, 0.0],
+            spread: 80.0,
+            speed: [0.2, 0.6],
+            rate: 20.0,
+            lifetime: 4.0,
+            gravity_scale: -0.02,
+            drag: 1.5,
+            start_color: [180.0, 160.0, 130.0],
+            end_color: [200.0, 190.0, 170.0],
+            start_size: 0.05,
+            end_size: 0.2,
+            start_emission: 0.0,
+            end_emission: 0.0,
+            max_particles: 128,
+        }
+    }
+
+    // Heavy chunks thrown out in a wide cone
+    pub fn debris() -> Self {
+        ParticleEmitter {
+            is_enabled: true,
+            position: [0.0, 0.5, 0.0],
+            direction: [0.0, 1.0, 0.0],
+            spread: 60.0,
+            speed: [3.0, 6.0],
+            rate: 15.0,
+            lifetime: 2.5,
+            gravity_scale: 1.0,
+            drag: 0.05,
+            start_color: [90.0, 80.0, 70.0],
+            end_color: [90.0, 80.0, 70.0],
+            start_size: 0.08,
+            end_size: 0.06,
+            start_emission: 0.0,
+            end_emission: 0.0,
+            max_particles: 64,
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.is_enabled, "Enabled");
+        ui.horizontal(|ui| {
+            ui.label("Position");
+            for value in self.position.iter_mut() {
+                ui.add(egui::DragValue::new(value).speed(0.1));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Direction");
+            for value in self.direction.iter_mut() {
+                ui.add(egui::DragValue::new(value).speed(0.01));
+            }
+        });
+        ui.add(Slider::new(&mut self.spread, 0.0..=180.0).text("Spread"));
+        ui.add(Slider::new(&mut self.speed[0], 0.0..=50.0).text("Min Speed"));
+        ui.add(Slider::new(&mut self.speed[1], 0.0..=50.0).text("Max Speed"));
+        ui.add(Slider::new(&mut self.rate, 0.0..=500.0).text("Rate"));
+        ui.add(Slider::new(&mut self.lifetime, 0.05..=20.0).text("Lifetime"));
+        ui.add(Slider::new(&mut self.gravity_scale, -1.0..=2.0).text("Gravity"));
+        ui.add(Slider::new(&mut self.drag, 0.0..=10.0).text("Drag"));
+        ui.horizontal(|ui| {
+            ui.label("Color");
+            color_edit(ui, &mut self.start_color);
+            ui.label("→");
+            color_edit(ui, &mut self.end_color);
+        });
+        ui.add(Slider::new(&mut self.start_size, 0.001..=1.0).text("Start Size").logarithmic(true));
+        ui.add(Slider::new(&mut self.end_size, 0.001..=1.0).text("End Size").logarithmic(true));
+        ui.add(Slider::new(&mut self.start_emission, 0.0..=100.0).text("Start Emission"));
+        ui.add(Slider::new(&mut self.end_emission, 0.0..=100.0).text("End Emission"));
+        ui.add(Slider::new(&mut self.max_particles, 1..=2048).text("Max Particles"));
+    }
+}
+
+// Edits a 0-255 color like the object colors
+fn color_edit(ui: &mut Ui, color: &mut [f32; 3]) {
+    let mut rgb = color.map(|c| c / 255.0);
+    if ui.color_edit_button_rgb(&mut rgb).changed() {
+        *color = rgb.map(|c| c * 255.0);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    position: [f32; 3],
+    velocity: [f32; 3],
+    age: f32,
+}
+
+// Live particles of every emitter, indexed like the emitters
+#[derive(Clone)]
+pub struct ParticleSystem {
+    particles: Vec<Vec<Particle>>,
+    // Fraction of a particle each emitter still owes
+    pending: Vec<f32>,
+    rng: u32,
+}
+
+impl ParticleSystem {
+    pub fn new() -> Self {
+        ParticleSystem {
+            particles: Vec::new(),
+            pending: Vec::new(),
+            rng: 1,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.particles.iter().map(Vec::len).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.pending.clear();
+    }
+
+    // Drops the particles of an emitter that was removed
+    pub fn remove(&mut self, emitter: usize) {
+        if emitter < self.particles.len() {
+            self.particles.remove(emitter);
+            self.pending.remove(emitter);
+        }
+    }
+
+    // Ages and moves the particles, then spawns the ones due in `dt`
+    pub fn update(&mut self, emitters: &[ParticleEmitter], gravity: [f32; 3], dt: f32) {
+        self.particles.resize(emitters.len(), Vec::new());
+        self.pending.resize(emitters.len(), 0.0);
+        for (e, emitter) in emitters.iter().enumerate() {
+            let acceleration = scale(gravity, emitter.gravity_scale);
+            let damping = 1.0 / (1.0 + emitter.drag * dt);
+            let particles = &mut self.particles[e];
+            particles.retain_mut(|particle| {
+                particle.age += dt;
+                particle.velocity = scale(add(particle.velocity, scale(acceleration, dt)), damping);
+                particle.position = add(particle.position, scale(particle.velocity, dt));
+                particle.age < emitter.lifetime
+            });
+
+            if !emitter.is_enabled {
+                self.pending[e] = 0.0;
+                continue;
+            }
+            self.pending[e] += emitter.rate * dt;
+            while self.pending[e] >= 1.0 {
+                self.pending[e] -= 1.0;
+                if self.particles[e].len() >= emitter.max_particles as usize {
+                    continue;
+                }
+                let velocity = scale(self.cone_direction(emitter), self.lerp_random(emitter.speed));
+                // Spread the spawns over the frame so bursts don't leave in sheets
+                let age = self.random() * dt;
+                self.particles[e].push(Particle {
+                    position: add(emitter.position, scale(velocity, age)),
+                    velocity,
+                    age,
+                });
+            }
+        }
+    }
+
+    // One sphere per live particle for the ray tracer
+    pub fn spheres(&self, emitters: &[ParticleEmitter]) -> Vec<Object> {
+        let mut spheres = Vec::with_capacity(self.count());
+        for (emitter, particles) in emitters.iter().zip(self.particles.iter()) {
+            for particle in particles.iter() {
+                let t = (particle.age / emitter.lifetime).min(1.0);
+                let mut color = emitter.start_color;
+                for (channel, end) in color.iter_mut().zip(emitter.end_color) {
+                    *channel = lerp(*channel, end, t);
+                }
+                let mut sphere = Object::new(
+                    particle.position,
+                    lerp(emitter.start_size, emitter.end_size, t),
+                    color,
+                    1.0,
+                    lerp(emitter.start_emission, emitter.end_emission, t),
+                    true,
+                );
+                sphere.velocity = particle.velocity;
+                spheres.push(sphere);
+            }
+        }
+        spheres
+    }
+
+    fn random(&mut self) -> f32 {
+        self.rng = pcg_hash(self.rng);
+        (self.rng >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    fn lerp_random(&mut self, range: [f32; 2]) -> f32 {
+        lerp(range[0], range[1], self.random())
+    }
+
+    // Uniformly distributed over the spherical cap around the emitter direction
+    fn cone_direction(&mut self, emitter: &ParticleEmitter) -> [f32; 3] {
+        let axis = normalize(emitter.direction);
+        let helper = if axis[0].abs() > 0.9 { [0.0, 1.0, 0.0] } else { [1.0, 0.0, 0.0] };
+        let u = normalize(cross(helper, axis));
+        let v = cross(axis, u);
+
+        let cos_spread = emitter.spread.to_radians().cos();
+        let cos_theta = 1.0 - self.random() * (1.0 - cos_spread);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * self.random();
+        add(
+            add(scale(u, phi.cos() * sin_theta), scale(v, phi.sin() * sin_theta)),
+            scale(axis, cos_theta),
+        )
+    }
+}
+
+impl Default for ParticleSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
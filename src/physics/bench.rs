@@ -0,0 +1,49 @@
+use std::time::Instant;
+
+use crate::object::Object;
+
+use super::world::PhysicsWorld;
+
+// Body counts timed when none is given
+pub const DEFAULT_BODY_COUNTS: [usize; 2] = [4000, 8000];
+
+// Drops a grid of alternating boxes and spheres onto a ground plane and
+// reports the time per step, run with `--bench-physics [bodies] [steps]`
+pub fn run(body_count: usize, steps: u32) {
+    if cfg!(debug_assertions) {
+        println!("Debug build, build with --release for meaningful timings");
+    }
+    let mut world = PhysicsWorld::new();
+    world.settings.has_ground = true;
+
+    let side = (body_count as f32).cbrt().ceil() as usize;
+    let mut bodies = Vec::with_capacity(body_count);
+    for i in 0..body_count {
+        let (x, y, z) = (i % side, i / (side * side), (i / side) % side);
+        let position = [x as f32 * 1.5, 1.0 + y as f32 * 1.5, z as f32 * 1.5];
+        let mut body = Object::new(position, 0.5, [200.0; 3], 1.0, 0.0, false);
+        body.is_cube = i % 2 == 0;
+        bodies.push(body);
+    }
+    world.sync_from_scene(&bodies);
+
+    let start = Instant::now();
+    let mut slowest = 0.0f32;
+    for _ in 0..steps {
+        let step_start = Instant::now();
+        world.step(world.fixed_dt);
+        slowest = slowest.max(step_start.elapsed().as_secs_f32());
+    }
+    let average = start.elapsed().as_secs_f32() / steps.max(1) as f32;
+    let asleep = world.bodies.iter().filter(|b| b.is_sleeping).count();
+    println!(
+        "{} bodies, {} steps of {:.2} ms: average {:.3} ms, slowest {:.3} ms, {}, {} asleep at the end",
+        body_count,
+        steps,
+        world.fixed_dt * 1000.0,
+        average * 1000.0,
+        slowest * 1000.0,
+        if average <= world.fixed_dt { "real time" } else { "slower than real time" },
+        asleep,
+    );
+}
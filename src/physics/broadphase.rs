@@ -0,0 +1,71 @@
+use crate::bvh::Aabb;
+
+// Sweep and prune along the axis where the bodies are most spread out. The
+// sorted order is kept between steps, bodies move little per step so the
+// insertion sort is close to linear.
+pub struct SweepAndPrune {
+    axis: usize,
+    order: Vec<usize>,
+}
+
+impl SweepAndPrune {
+    pub fn new() -> Self {
+        SweepAndPrune { axis: 0, order: Vec::new() }
+    }
+
+    // Pairs (i, j) with i < j whose boxes overlap, sorted so the narrow phase runs in a fixed order
+    pub fn pairs(&mut self, bounds: &[Aabb]) -> Vec<(usize, usize)> {
+        let axis = sweep_axis(bounds);
+        if self.order.len() != bounds.len() || axis != self.axis {
+            // Full sort when the order isn't coherent with the last step
+            self.axis = axis;
+            self.order = (0..bounds.len()).collect();
+            self.order.sort_by(|&a, &b| bounds[a].min[axis].total_cmp(&bounds[b].min[axis]));
+        } else {
+            for i in 1..self.order.len() {
+                let mut j = i;
+                while j > 0 && bounds[self.order[j - 1]].min[axis] > bounds[self.order[j]].min[axis] {
+                    self.order.swap(j - 1, j);
+                    j -= 1;
+                }
+            }
+        }
+
+        // Contiguous copy in sweep order keeps the inner loop in cache
+        let sorted: Vec<Aabb> = self.order.iter().map(|&i| bounds[i]).collect();
+        let mut pairs = Vec::new();
+        for (i, a) in sorted.iter().enumerate() {
+            for (j, b) in sorted.iter().enumerate().skip(i + 1) {
+                if b.min[axis] > a.max[axis] {
+                    break;
+                }
+                if overlaps(a, b) {
+                    let (a, b) = (self.order[i], self.order[j]);
+                    pairs.push((a.min(b), a.max(b)));
+                }
+            }
+        }
+        pairs.sort_unstable();
+        pairs
+    }
+}
+
+// Axis with the largest variance of box centers
+fn sweep_axis(bounds: &[Aabb]) -> usize {
+    let mut sum = [0.0f32; 3];
+    let mut sum_squared = [0.0f32; 3];
+    for aabb in bounds {
+        let center = aabb.centroid();
+        for axis in 0..3 {
+            sum[axis] += center[axis];
+            sum_squared[axis] += center[axis] * center[axis];
+        }
+    }
+    let n = bounds.len().max(1) as f32;
+    let variance = |axis: usize| sum_squared[axis] / n - (sum[axis] / n).powi(2);
+    (0..3).fold(0, |best, axis| if variance(axis) > variance(best) { axis } else { best })
+}
+
+pub fn overlaps(a: &Aabb, b: &Aabb) -> bool {
+    (0..3).all(|axis| a.min[axis] <= b.max[axis] && b.min[axis] <= a.max[axis])
+}
@@ -0,0 +1,123 @@
+// Continuous collision detection for bodies that move further than their
+// own size in a step, and the sweep behind shape casts. A probe shape is
+// advanced conservatively along its motion until it touches a target: the
+// gap can't close faster than the probe moves, so stepping by the gap never
+// skips past a hit, which stops fast projectiles from passing through thin walls.
+
+use crate::bvh::Aabb;
+use crate::object::Object;
+
+use super::collision::{plane_separation, separation, Plane, Shape};
+use super::math::{add, length, scale};
+
+// Bodies moving more than this fraction of their inner radius per step are swept
+const MOTION_FRACTION: f32 = 0.5;
+// Gap at which the sweep counts as touching
+const TOLERANCE: f32 = 0.005;
+
+pub fn needs_sweep(body: &Object, displacement: [f32; 3]) -> bool {
+    length(displacement) > MOTION_FRACTION * Shape::of(body).inner_radius()
+}
+
+// Largest sphere inside the body, swept in its place so rotation doesn't matter
+pub fn inner_sphere(body: &Object) -> Object {
+    let mut probe = *body;
+    probe.radius = Shape::of(body).inner_radius();
+    probe.is_cube = false;
+    probe
+}
+
+// Earliest fraction of `displacement` at which the probe touches `target`,
+// None if it doesn't within the motion or already touches it at the start
+pub fn time_of_impact(probe: &Object, displacement: [f32; 3], target: &Object) -> Option<f32> {
+    advance(|t| separation(&moved(probe, displacement, t), target), length(displacement))
+}
+
+pub fn time_of_impact_plane(probe: &Object, displacement: [f32; 3], plane: &Plane) -> Option<f32> {
+    advance(|t| plane_separation(&moved(probe, displacement, t), plane), length(displacement))
+}
+
+fn moved(probe: &Object, displacement: [f32; 3], t: f32) -> Object {
+    let mut body = *probe;
+    body.position = add(probe.position, scale(displacement, t));
+    body
+}
+
+fn advance(gap_at: impl Fn(f32) -> f32, motion: f32) -> Option<f32> {
+    if gap_at(0.0) <= TOLERANCE || motion == 0.0 {
+        return None;
+    }
+    // Each step advances at least the tolerance, so the loop ends within
+    // motion / TOLERANCE steps even while crawling along a shallow approach
+    let mut t = 0.0;
+    loop {
+        let gap = gap_at(t);
+        if gap <= TOLERANCE {
+            return Some(t);
+        }
+        t += gap.max(TOLERANCE) / motion;
+        if t > 1.0 {
+            return None;
+        }
+    }
+}
+
+// Fraction of the motion just past the time of impact, so the probe
+// overlaps the target slightly and the narrow phase sees the contact
+pub fn past_impact(displacement: [f32; 3], t: f32) -> f32 {
+    (t + 2.0 * TOLERANCE / length(displacement)).min(1.0)
+}
+
+// Bounds of the probe over its whole motion
+pub fn swept_bounds(probe: &Object, displacement: [f32; 3]) -> Aabb {
+    let shape = Shape::of(probe);
+    shape.bounds(probe).union(&shape.bounds(&moved(probe, displacement, 1.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(position: [f32; 3], radius: f32) -> Object {
+        let mut body = Object::new(position, radius, [0.0; 3], 1.0, 0.0, false);
+        body.is_cube = false;
+        body
+    }
+
+    fn unit_box() -> Object {
+        Object::new([0.0; 3], 0.0, [0.0; 3], 1.0, 0.0, true)
+    }
+
+    #[test]
+    fn grazing_miss_has_no_impact() {
+        // Passes over the top face with 0.02 clearance
+        for distance in [10.0, 100.0] {
+            let probe = sphere([-distance / 2.0, 1.02, 0.0], 0.5);
+            assert_eq!(time_of_impact(&probe, [distance, 0.0, 0.0], &unit_box()), None);
+        }
+    }
+
+    #[test]
+    fn shallow_approach_hits_the_floor() {
+        // Descends 2 units over 100 toward a wide floor whose top is at y = 0.5,
+        // so the gap closes slowly and the sweep takes many small steps
+        let probe = sphere([-50.0, 2.0, 0.0], 0.5);
+        let mut floor = unit_box();
+        floor.size = [200.0, 1.0, 200.0];
+        let direction = [100.0, -2.0, 0.0];
+        let displacement = scale(direction, 200.0 / length(direction));
+        let t = time_of_impact(&probe, displacement, &floor).expect("sweep should hit the floor");
+        // Touches once the center has dropped 1 unit, 50 units along x. The gap
+        // closes 50 times slower than the probe moves, so the tolerance stretches as much
+        let expected = 50.0 * length(direction) / 100.0;
+        assert!((t * 200.0 - expected).abs() <= 50.0 * TOLERANCE, "distance = {}", t * 200.0);
+    }
+
+    #[test]
+    fn head_on_hit_stops_at_the_face() {
+        let probe = sphere([-5.0, 0.0, 0.0], 0.5);
+        let t = time_of_impact(&probe, [10.0, 0.0, 0.0], &unit_box()).expect("sweep should hit the box");
+        // Touches when the center reaches x = -1, 4 of the 10 units in
+        assert!((t - 0.4).abs() * 10.0 <= TOLERANCE, "t = {}", t);
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::HashMap;
+use std::io;
+
+use egui::{Slider, Ui};
+
+use crate::bvh::Aabb;
+use crate::mesh::{Instance, Mesh, MeshTriangle};
+use crate::object::Object;
+
+use super::broadphase::overlaps;
+use super::collision::Shape;
+use super::math::{add, cross, dot, length, normalize, quat_rotate, quat_rotate_inverse, scale, sub};
+use super::settings::PhysicsSettings;
+
+// Cloth and soft bodies simulated with XPBD (Macklin et al. 2016, "XPBD:
+// Position-Based Simulation of Compliant Constrained Dynamics"). The welded
+// vertices of a triangle mesh are particles held together by distance
+// constraints along the edges and across neighbouring triangles, closed
+// meshes can also keep their volume. Each step is split into substeps with
+// one constraint pass each, which converges better than iterating. Particles
+// are pushed out of the rigid bodies and the ground, the bodies don't feel
+// the cloth. The mesh is rebuilt with the particles, updates move its
+// vertices in place and refit its BLAS so only they go to the GPU again.
+
+const SUBSTEPS: usize = 10;
+// Frame times above this are clamped like in the rigid body world
+const MAX_FRAME_TIME: f32 = 1.0 / 30.0;
+// Grid of the source "grid" when no mesh is given, in the XY plane
+pub const GRID_SOURCE: &str = "grid";
+// Positions closer than this are welded into one particle
+const WELD_DISTANCE: f32 = 1e-4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClothPins {
+    None,
+    // The two ends of the top row
+    TopCorners,
+    // The whole top row
+    TopEdge,
+}
+
+impl ClothPins {
+    pub const ALL: [ClothPins; 3] = [ClothPins::None, ClothPins::TopCorners, ClothPins::TopEdge];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClothPins::None => "none",
+            ClothPins::TopCorners => "corners",
+            ClothPins::TopEdge => "edge",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ClothPins> {
+        ClothPins::ALL.iter().copied().find(|pins| pins.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct DistanceConstraint {
+    a: usize,
+    b: usize,
+    rest_length: f32,
+}
+
+#[derive(Clone)]
+pub struct Cloth {
+    // GRID_SOURCE, a built in mesh or an OBJ path, see Mesh::load
+    pub source: String,
+    pub position: [f32; 3],
+    // Euler angles in degrees like mesh instances, the grid hangs in the XY plane
+    pub rotation: [f32; 3],
+    // Side length of the grid, or the scale of a loaded mesh
+    pub size: f32,
+    // Quads per side of the grid
+    pub segments: u32,
+    pub pins: ClothPins,
+    // Inverse stiffness of the edges and of the bending, 0 is rigid
+    pub stretch_compliance: f32,
+    pub bend_compliance: f32,
+    // Volume kept relative to the rest volume, 0 lets a closed mesh collapse
+    pub pressure: f32,
+    // Mass of the whole cloth in kg
+    pub mass: f32,
+    // Distance kept from rigid bodies and the ground
+    pub thickness: f32,
+    pub friction: f32,
+    pub color: [f32; 3],
+    pub roughness: f32,
+    // Deformed mesh in world space, drawn through an identity instance
+    pub mesh: Mesh,
+    // Particles as built, what reset returns to
+    rest: Vec<[f32; 3]>,
+    positions: Vec<[f32; 3]>,
+    previous: Vec<[f32; 3]>,
+    velocities: Vec<[f32; 3]>,
+    inverse_masses: Vec<f32>,
+    triangles: Vec<[usize; 3]>,
+    edges: Vec<DistanceConstraint>,
+    bends: Vec<DistanceConstraint>,
+    rest_volume: f32,
+}
+
+impl Cloth {
+    // A hanging sheet for the grid source, a pressurized soft body for meshes
+    pub fn new(source: &str, position: [f32; 3]) -> Result<Cloth, io::Error> {
+        let is_grid = source == GRID_SOURCE;
+        let mut cloth = Cloth {
+            source: source.to_string(),
+            position,
+            rotation: [0.0; 3],
+            size: if is_grid { 2.0 } else { 0.5 },
+            segments: 20,
+            pins: if is_grid { ClothPins::TopCorners } else { ClothPins::None },
+            stretch_compliance: if is_grid { 0.0 } else { 1e-4 },
+            bend_compliance: if is_grid { 1e-2 } else { 1e-3 },
+            pressure: if is_grid { 0.0 } else { 1.0 },
+            mass: 1.0,
+            thickness: 0.02,
+            friction: 0.3,
+            color: [200.0, 60.0, 60.0],
+            roughness: 0.8,
+            mesh: Mesh::from_triangles(source, Vec::new()),
+            rest: Vec::new(),
+            positions: Vec::new(),
+            previous: Vec::new(),
+            velocities: Vec::new(),
+            inverse_masses: Vec::new(),
+            triangles: Vec::new(),
+            edges: Vec::new(),
+            bends: Vec::new(),
+            rest_volume: 0.0,
+        };
+        cloth.rebuild()?;
+        Ok(cloth)
+    }
+
+    // Recreates the particles and constraints from the source at rest
+    pub fn rebuild(&mut self) -> Result<(), io::Error> {
+        let corners: Vec<[[f32; 3]; 3]> = if self.source == GRID_SOURCE {
+            grid_triangles(self.segments.max(1) as usize)
+        } else {
+            Mesh::load(&self.source)?.triangles.iter().map(|triangle| triangle.vertices).collect()
+        };
+
+        // Weld the corners shared by triangles into particles
+        let mut placement = Instance::new(0, self.position);
+        placement.rotation = self.rotation;
+        placement.scale = self.size;
+        let transform = placement.transform();
+        let mut welded: HashMap<[i32; 3], usize> = HashMap::new();
+        self.positions.clear();
+        self.triangles.clear();
+        for triangle in corners.iter() {
+            let mut indices = [0; 3];
+            for (index, corner) in indices.iter_mut().zip(triangle.iter()) {
+                let point = [0, 1, 2].map(|row| {
+                    transform[3][row] + (0..3).map(|column| transform[column][row] * corner[column]).sum::<f32>()
+                });
+                let key = point.map(|x| (x / WELD_DISTANCE).round() as i32);
+                *index = *welded.entry(key).or_insert_with(|| {
+                    self.positions.push(point);
+                    self.positions.len() - 1
+                });
+            }
+            if indices[0] != indices[1] && indices[1] != indices[2] && indices[0] != indices[2] {
+                self.triangles.push(indices);
+            }
+        }
+        let count = self.positions.len();
+        self.rest = self.positions.clone();
+        self.previous = self.positions.clone();
+        self.velocities = vec![[0.0; 3]; count];
+
+        // Edges, and a bending constraint between the far corners of every two triangles sharing one
+        let mut opposite: HashMap<(usize, usize), usize> = HashMap::new();
+        self.edges.clear();
+        self.bends.clear();
+        for triangle in self.triangles.iter() {
+            for k in 0..3 {
+                let (a, b, c) = (triangle[k], triangle[(k + 1) % 3], triangle[(k + 2) % 3]);
+                let key = (a.min(b), a.max(b));
+                match opposite.get(&key) {
+                    None => {
+                        opposite.insert(key, c);
+                        self.edges.push(constraint(&self.positions, a, b));
+                    }
+                    Some(&d) if d != c => self.bends.push(constraint(&self.positions, c, d)),
+                    Some(_) => {}
+                }
+            }
+        }
+
+        let inverse_mass = count as f32 / self.mass.max(1e-6);
+        let pinned = pinned(&self.positions, self.pins);
+        self.inverse_masses = (0..count).map(|i| if pinned.contains(&i) { 0.0 } else { inverse_mass }).collect();
+        self.rest_volume = self.volume();
+        self.mesh = Mesh::from_triangles(&self.source, self.mesh_triangles());
+        Ok(())
+    }
+
+    // Puts the particles back where they were built, at rest
+    pub fn reset(&mut self) {
+        self.positions.clone_from(&self.rest);
+        self.previous.clone_from(&self.rest);
+        self.velocities.iter_mut().for_each(|velocity| *velocity = [0.0; 3]);
+        self.update_mesh();
+    }
+
+    // Advances the particles by the frame time against the rigid bodies
+    pub fn update(&mut self, frame_time: f32, bodies: &[Object], settings: &PhysicsSettings) {
+        let dt = frame_time.min(MAX_FRAME_TIME);
+        if dt <= 0.0 || self.positions.is_empty() {
+            return;
+        }
+        let h = dt / SUBSTEPS as f32;
+        for _ in 0..SUBSTEPS {
+            for i in 0..self.positions.len() {
+                if self.inverse_masses[i] == 0.0 {
+                    continue;
+                }
+                self.velocities[i] = add(self.velocities[i], scale(settings.gravity, h));
+                self.previous[i] = self.positions[i];
+                self.positions[i] = add(self.positions[i], scale(self.velocities[i], h));
+            }
+
+            solve_distances(&mut self.positions, &self.inverse_masses, &self.edges, self.stretch_compliance, h);
+            solve_distances(&mut self.positions, &self.inverse_masses, &self.bends, self.bend_compliance, h);
+            if self.pressure > 0.0 && self.rest_volume.abs() > 1e-9 {
+                self.solve_volume();
+            }
+            self.collide(bodies, settings);
+
+            for i in 0..self.positions.len() {
+                if self.inverse_masses[i] != 0.0 {
+                    self.velocities[i] = scale(sub(self.positions[i], self.previous[i]), 1.0 / h);
+                }
+            }
+        }
+        self.update_mesh();
+    }
+
+    // Identity placement of the deformed mesh, stored at index `mesh`
+    pub fn instance(&self, mesh: usize) -> Instance {
+        let mut instance = Instance::new(mesh, [0.0; 3]);
+        instance.override_material = true;
+        instance.color = self.color;
+        instance.roughness = self.roughness;
+        instance
+    }
+
+    // Signed volume enclosed by the triangles, only meaningful for closed meshes
+    fn volume(&self) -> f32 {
+        self.triangles
+            .iter()
+            .map(|t| dot(cross(self.positions[t[0]], self.positions[t[1]]), self.positions[t[2]]) / 6.0)
+            .sum()
+    }
+
+    // A single constraint on the enclosed volume, moving every particle along its gradient
+    fn solve_volume(&mut self) {
+        let mut gradients = vec![[0.0; 3]; self.positions.len()];
+        for t in self.triangles.iter() {
+            let p = [self.positions[t[0]], self.positions[t[1]], self.positions[t[2]]];
+            for k in 0..3 {
+                let gradient = scale(cross(p[(k + 1) % 3], p[(k + 2) % 3]), 1.0 / 6.0);
+                gradients[t[k]] = add(gradients[t[k]], gradient);
+            }
+        }
+        let weight: f32 = gradients
+            .iter()
+            .zip(self.inverse_masses.iter())
+            .map(|(gradient, w)| w * dot(*gradient, *gradient))
+            .sum();
+        if weight < 1e-12 {
+            return;
+        }
+        let lambda = -(self.volume() - self.pressure * self.rest_volume) / weight;
+        for ((position, gradient), w) in self.positions.iter_mut().zip(gradients.iter()).zip(self.inverse_masses.iter()) {
+            *position = add(*position, scale(*gradient, lambda * w));
+        }
+    }
+
+    // Pushes particles out of the ground and of every body near the cloth,
+    // friction takes back part of the sliding since the substep began
+    fn collide(&mut self, bodies: &[Object], settings: &PhysicsSettings) {
+        let mut bounds = Aabb::from_points(&self.positions);
+        bounds.min = sub(bounds.min, [self.thickness; 3]);
+        bounds.max = add(bounds.max, [self.thickness; 3]);
+        let nearby: Vec<&Object> = bodies
+            .iter()
+            .filter(|body| overlaps(&bounds, &Shape::of(body).bounds(body)))
+            .collect();
+        let ground = settings.ground_plane();
+
+        for i in 0..self.positions.len() {
+            if self.inverse_masses[i] == 0.0 {
+                continue;
+            }
+            let mut normal = None;
+            if let Some(plane) = ground {
+                let depth = dot(plane.normal, self.positions[i]) - plane.offset - self.thickness;
+                if depth < 0.0 {
+                    self.positions[i] = sub(self.positions[i], scale(plane.normal, depth));
+                    normal = Some(plane.normal);
+                }
+            }
+            for body in nearby.iter() {
+                if let Some((surface, body_normal)) = push_out(body, self.positions[i], self.thickness) {
+                    self.positions[i] = surface;
+                    normal = Some(body_normal);
+                }
+            }
+            if let Some(normal) = normal {
+                let moved = sub(self.positions[i], self.previous[i]);
+                let sliding = sub(moved, scale(normal, dot(moved, normal)));
+                self.positions[i] = sub(self.positions[i], scale(sliding, self.friction.clamp(0.0, 1.0)));
+            }
+        }
+    }
+
+    // Moves the mesh vertices to the particles, the mesh stores the triangles in BLAS leaf order
+    fn update_mesh(&mut self) {
+        let triangles = self.mesh_triangles();
+        for (slot, &t) in self.mesh.triangles.iter_mut().zip(self.mesh.bvh.order.iter()) {
+            *slot = triangles[t];
+        }
+        self.mesh.refit();
+    }
+
+    // Smooth normals from the surrounding triangles, facing either way is fine
+    // since the ray tracer flips normals against the ray
+    fn mesh_triangles(&self) -> Vec<MeshTriangle> {
+        let mut normals = vec![[0.0; 3]; self.positions.len()];
+        for t in self.triangles.iter() {
+            let p = [self.positions[t[0]], self.positions[t[1]], self.positions[t[2]]];
+            let face = cross(sub(p[1], p[0]), sub(p[2], p[0]));
+            for &i in t.iter() {
+                normals[i] = add(normals[i], face);
+            }
+        }
+        self.triangles
+            .iter()
+            .map(|t| MeshTriangle {
+                vertices: t.map(|i| self.positions[i]),
+                normals: t.map(|i| normalize(normals[i])),
+            })
+            .collect()
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        let mut needs_rebuild = false;
+        ui.horizontal(|ui| {
+            ui.label("Source");
+            ui.text_edit_singleline(&mut self.source)
+                .on_hover_text("grid, a built in mesh or an OBJ path");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Position");
+            for value in self.position.iter_mut() {
+                needs_rebuild |= ui.add(egui::DragValue::new(value).speed(0.1)).changed();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Rotation");
+            for value in self.rotation.iter_mut() {
+                needs_rebuild |= ui.add(egui::DragValue::new(value).speed(1.0).suffix("°")).changed();
+            }
+        });
+        needs_rebuild |= ui.add(Slider::new(&mut self.size, 0.05..=20.0).text("Size")).changed();
+        if self.source == GRID_SOURCE {
+            needs_rebuild |= ui.add(Slider::new(&mut self.segments, 1..=64).text("Segments")).changed();
+        }
+        egui::ComboBox::from_label("Pinned")
+            .selected_text(self.pins.name())
+            .show_ui(ui, |ui| {
+                for pins in ClothPins::ALL.iter() {
+                    needs_rebuild |= ui.selectable_value(&mut self.pins, *pins, pins.name()).changed();
+                }
+            });
+        ui.add(Slider::new(&mut self.stretch_compliance, 0.0..=0.1).text("Stretch Compliance").logarithmic(true));
+        ui.add(Slider::new(&mut self.bend_compliance, 0.0..=10.0).text("Bend Compliance").logarithmic(true));
+        ui.add(Slider::new(&mut self.pressure, 0.0..=3.0).text("Pressure"))
+            .on_hover_text("Volume kept by closed meshes, 0 for none");
+        needs_rebuild |= ui.add(Slider::new(&mut self.mass, 0.01..=100.0).text("Mass").logarithmic(true)).changed();
+        ui.add(Slider::new(&mut self.thickness, 0.0..=0.2).text("Thickness"));
+        ui.add(Slider::new(&mut self.friction, 0.0..=1.0).text("Friction"));
+        ui.horizontal(|ui| {
+            ui.label("Color");
+            let mut color = [self.color[0] / 255.0, self.color[1] / 255.0, self.color[2] / 255.0];
+            if ui.color_edit_button_rgb(&mut color).changed() {
+                self.color = [color[0] * 255.0, color[1] * 255.0, color[2] * 255.0];
+            }
+        });
+        ui.add(Slider::new(&mut self.roughness, 0.0..=1.0).text("Roughness"));
+        needs_rebuild |= ui.button("Reset").on_hover_text("Back to the rest shape, also reloads the source").clicked();
+        if needs_rebuild {
+            if let Err(e) = self.rebuild() {
+                eprintln!("Error building cloth: {}", e);
+            }
+        }
+    }
+}
+
+fn constraint(positions: &[[f32; 3]], a: usize, b: usize) -> DistanceConstraint {
+    DistanceConstraint {
+        a,
+        b,
+        rest_length: length(sub(positions[a], positions[b])),
+    }
+}
+
+fn solve_distances(positions: &mut [[f32; 3]], inverse_masses: &[f32], constraints: &[DistanceConstraint], compliance: f32, h: f32) {
+    let alpha = compliance / (h * h);
+    for c in constraints.iter() {
+        let (wa, wb) = (inverse_masses[c.a], inverse_masses[c.b]);
+        let w = wa + wb;
+        if w == 0.0 {
+            continue;
+        }
+        let delta = sub(positions[c.a], positions[c.b]);
+        let distance = length(delta);
+        if distance < 1e-9 {
+            continue;
+        }
+        let n = scale(delta, 1.0 / distance);
+        let lambda = -(distance - c.rest_length) / (w + alpha);
+        positions[c.a] = add(positions[c.a], scale(n, lambda * wa));
+        positions[c.b] = sub(positions[c.b], scale(n, lambda * wb));
+    }
+}
+
+// Closest point on the surface of the body grown by `thickness` and its
+// outward normal, None if the point is outside
+fn push_out(body: &Object, point: [f32; 3], thickness: f32) -> Option<([f32; 3], [f32; 3])> {
+    match Shape::of(body) {
+        Shape::Sphere { radius } => {
+            let offset = sub(point, body.position);
+            let distance = length(offset);
+            if distance >= radius + thickness {
+                return None;
+            }
+            let normal = if distance > 1e-9 { scale(offset, 1.0 / distance) } else { [0.0, 1.0, 0.0] };
+            Some((add(body.position, scale(normal, radius + thickness)), normal))
+        }
+        Shape::Box { half_extents } => {
+            let mut local = quat_rotate_inverse(body.orientation, sub(point, body.position));
+            let half = half_extents.map(|h| h + thickness);
+            if (0..3).any(|axis| local[axis].abs() >= half[axis]) {
+                return None;
+            }
+            // Out through the nearest face
+            let axis = (0..3)
+                .min_by(|&a, &b| (half[a] - local[a].abs()).total_cmp(&(half[b] - local[b].abs())))
+                .unwrap();
+            let side = if local[axis] < 0.0 { -1.0 } else { 1.0 };
+            local[axis] = side * half[axis];
+            let mut normal = [0.0; 3];
+            normal[axis] = side;
+            Some((add(body.position, quat_rotate(body.orientation, local)), quat_rotate(body.orientation, normal)))
+        }
+    }
+}
+
+// Unit square in the XY plane centered on the origin, top row at y = 0.5
+fn grid_triangles(segments: usize) -> Vec<[[f32; 3]; 3]> {
+    let point = |x: usize, y: usize| [x as f32 / segments as f32 - 0.5, y as f32 / segments as f32 - 0.5, 0.0];
+    let mut triangles = Vec::with_capacity(segments * segments * 2);
+    for y in 0..segments {
+        for x in 0..segments {
+            let (p00, p10, p01, p11) = (point(x, y), point(x + 1, y), point(x, y + 1), point(x + 1, y + 1));
+            // Alternate the diagonal so the cloth doesn't fold preferentially one way
+            if (x + y) % 2 == 0 {
+                triangles.push([p00, p10, p11]);
+                triangles.push([p00, p11, p01]);
+            } else {
+                triangles.push([p00, p10, p01]);
+                triangles.push([p10, p11, p01]);
+            }
+        }
+    }
+    triangles
+}
+
+// Particles held in place: the highest row, or its two outermost particles along X
+fn pinned(positions: &[[f32; 3]], pins: ClothPins) -> Vec<usize> {
+    if pins == ClothPins::None || positions.is_empty() {
+        return Vec::new();
+    }
+    let (low, high) = positions.iter().fold((f32::MAX, f32::MIN), |(low, high), p| (low.min(p[1]), high.max(p[1])));
+    let tolerance = (high - low).max(1e-3) * 0.01;
+    let top: Vec<usize> = (0..positions.len()).filter(|&i| positions[i][1] >= high - tolerance).collect();
+    match pins {
+        ClothPins::TopEdge => top,
+        _ => {
+            let left = top.iter().copied().min_by(|&a, &b| positions[a][0].total_cmp(&positions[b][0]));
+            let right = top.iter().copied().max_by(|&a, &b| positions[a][0].total_cmp(&positions[b][0]));
+            let mut corners: Vec<usize> = left.into_iter().chain(right).collect();
+            corners.dedup();
+            corners
+        }
+    }
+}
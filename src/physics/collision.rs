@@ -0,0 +1,473 @@
+// Narrow phase tests between pairs of bodies and between bodies and planes.
+// Every test returns a manifold whose normal points from the first shape
+// towards the second, with up to MAX_CONTACTS points sharing that normal.
+
+use crate::bvh::Aabb;
+use crate::object::Object;
+
+use super::math::{add, cross, dot, length, normalize, quat_rotate, quat_rotate_inverse, scale, sub};
+
+const MAX_CONTACTS: usize = 4;
+// Edge axes only win over face axes by a margin, face contacts give steadier stacks
+const EDGE_AXIS_BIAS: f32 = 1.05;
+const EDGE_AXIS_SLOP: f32 = 0.001;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Sphere { radius: f32 },
+    Box { half_extents: [f32; 3] },
+}
+
+impl Shape {
+    pub fn of(body: &Object) -> Shape {
+        if body.is_cube {
+            Shape::Box { half_extents: scale(body.size, 0.5) }
+        } else {
+            Shape::Sphere { radius: body.radius }
+        }
+    }
+
+    // Radius of the sphere around the body position that contains the shape
+    pub fn bounding_radius(&self) -> f32 {
+        match *self {
+            Shape::Sphere { radius } => radius,
+            Shape::Box { half_extents } => length(half_extents),
+        }
+    }
+
+    // Principal moments of inertia of the solid shape about its center, along the body axes
+    pub fn inertia(&self, mass: f32) -> [f32; 3] {
+        match *self {
+            Shape::Sphere { radius } => [0.4 * mass * radius * radius; 3],
+            Shape::Box { half_extents: [x, y, z] } => [
+                mass * (y * y + z * z) / 3.0,
+                mass * (x * x + z * z) / 3.0,
+                mass * (x * x + y * y) / 3.0,
+            ],
+        }
+    }
+
+    // Radius of the largest sphere around the body position inside the shape
+    pub fn inner_radius(&self) -> f32 {
+        match *self {
+            Shape::Sphere { radius } => radius,
+            Shape::Box { half_extents } => half_extents[0].min(half_extents[1]).min(half_extents[2]),
+        }
+    }
+
+    // World space bounds of the shape placed at the body
+    pub fn bounds(&self, body: &Object) -> Aabb {
+        let extent = match *self {
+            Shape::Sphere { radius } => [radius; 3],
+            Shape::Box { half_extents } => {
+                let axes = box_axes(body.orientation);
+                let mut extent = [0.0; 3];
+                for (axis, e) in extent.iter_mut().enumerate() {
+                    *e = (0..3).map(|i| (axes[i][axis] * half_extents[i]).abs()).sum();
+                }
+                extent
+            }
+        };
+        Aabb {
+            min: sub(body.position, extent),
+            max: add(body.position, extent),
+        }
+    }
+}
+
+// Infinite static plane of points x with dot(normal, x) = offset, bodies rest on the normal side
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: [f32; 3],
+    pub offset: f32,
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+impl Plane {
+    pub fn new(normal: [f32; 3], offset: f32) -> Self {
+        Plane {
+            normal,
+            offset,
+            restitution: 0.3,
+            friction: 0.5,
+        }
+    }
+
+    // Static body standing in for the plane in the contact solver
+    pub fn as_body(&self) -> Object {
+        let mut body = Object::new(scale(self.normal, self.offset), 0.0, [0.0; 3], 1.0, 0.0, true);
+        body.restitution = self.restitution;
+        body.friction = self.friction;
+        body
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    pub point: [f32; 3],
+    pub penetration: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifold {
+    pub normal: [f32; 3],
+    pub contacts: Vec<Contact>,
+}
+
+impl Manifold {
+    fn single(normal: [f32; 3], point: [f32; 3], penetration: f32) -> Self {
+        Manifold {
+            normal,
+            contacts: vec![Contact { point, penetration }],
+        }
+    }
+
+    // Keeps the deepest contacts, None when there are none
+    fn from_contacts(normal: [f32; 3], mut contacts: Vec<Contact>) -> Option<Self> {
+        if contacts.is_empty() {
+            return None;
+        }
+        contacts.sort_by(|a, b| b.penetration.total_cmp(&a.penetration));
+        contacts.truncate(MAX_CONTACTS);
+        Some(Manifold { normal, contacts })
+    }
+
+    pub fn flipped(mut self) -> Self {
+        self.normal = scale(self.normal, -1.0);
+        self
+    }
+
+    pub fn max_penetration(&self) -> f32 {
+        self.contacts.iter().fold(0.0, |max, c| max.max(c.penetration))
+    }
+}
+
+pub fn collide(a: &Object, b: &Object) -> Option<Manifold> {
+    let shape_a = Shape::of(a);
+    let shape_b = Shape::of(b);
+    let reach = shape_a.bounding_radius() + shape_b.bounding_radius();
+    let delta = sub(b.position, a.position);
+    if dot(delta, delta) > reach * reach {
+        return None;
+    }
+    match (shape_a, shape_b) {
+        (Shape::Sphere { radius: ra }, Shape::Sphere { radius: rb }) => sphere_sphere(a.position, ra, b.position, rb),
+        (Shape::Sphere { radius }, Shape::Box { half_extents }) => {
+            sphere_box(a.position, radius, b.position, b.orientation, half_extents)
+        }
+        (Shape::Box { half_extents }, Shape::Sphere { radius }) => {
+            sphere_box(b.position, radius, a.position, a.orientation, half_extents).map(Manifold::flipped)
+        }
+        (Shape::Box { half_extents: ha }, Shape::Box { half_extents: hb }) => {
+            box_box(a.position, a.orientation, ha, b.position, b.orientation, hb)
+        }
+    }
+}
+
+// Manifold with the normal pointing from the body into the plane
+pub fn collide_plane(body: &Object, plane: &Plane) -> Option<Manifold> {
+    let n = plane.normal;
+    match Shape::of(body) {
+        Shape::Sphere { radius } => {
+            let distance = dot(n, body.position) - plane.offset;
+            let penetration = radius - distance;
+            if penetration <= 0.0 {
+                return None;
+            }
+            let point = sub(body.position, scale(n, radius - penetration * 0.5));
+            Some(Manifold::single(scale(n, -1.0), point, penetration))
+        }
+        Shape::Box { half_extents } => {
+            let contacts = box_vertices(body.position, body.orientation, half_extents)
+                .iter()
+                .filter_map(|&v| {
+                    let penetration = plane.offset - dot(n, v);
+                    if penetration > 0.0 {
+                        Some(Contact { point: add(v, scale(n, penetration * 0.5)), penetration })
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            Manifold::from_contacts(scale(n, -1.0), contacts)
+        }
+    }
+}
+
+// Lower bound on the gap between two shapes, 0 or less once they touch. Exact
+// when a sphere is involved, boxes use the widest gap between their
+// projections on the separating axes, which closes exactly as they touch.
+pub fn separation(a: &Object, b: &Object) -> f32 {
+    match (Shape::of(a), Shape::of(b)) {
+        (Shape::Sphere { radius: ra }, Shape::Sphere { radius: rb }) => length(sub(b.position, a.position)) - ra - rb,
+        (Shape::Sphere { radius }, Shape::Box { half_extents }) => point_box_distance(a.position, b, half_extents) - radius,
+        (Shape::Box { half_extents }, Shape::Sphere { radius }) => point_box_distance(b.position, a, half_extents) - radius,
+        (Shape::Box { half_extents: ha }, Shape::Box { half_extents: hb }) => {
+            let (axes_a, axes_b) = (box_axes(a.orientation), box_axes(b.orientation));
+            let delta = sub(b.position, a.position);
+            let mut candidates = Vec::with_capacity(15);
+            candidates.extend_from_slice(&axes_a);
+            candidates.extend_from_slice(&axes_b);
+            for edge_a in axes_a.iter() {
+                for edge_b in axes_b.iter() {
+                    candidates.push(cross(*edge_a, *edge_b));
+                }
+            }
+            candidates
+                .iter()
+                .filter(|axis| length(**axis) >= 1e-5)
+                .map(|axis| {
+                    let axis = normalize(*axis);
+                    dot(delta, axis).abs() - project_box(&axes_a, ha, axis) - project_box(&axes_b, hb, axis)
+                })
+                .fold(f32::MIN, f32::max)
+        }
+    }
+}
+
+// Gap between a body and the plane, negative when it sinks in
+pub fn plane_separation(body: &Object, plane: &Plane) -> f32 {
+    match Shape::of(body) {
+        Shape::Sphere { radius } => dot(plane.normal, body.position) - plane.offset - radius,
+        Shape::Box { half_extents } => box_vertices(body.position, body.orientation, half_extents)
+            .iter()
+            .map(|&v| dot(plane.normal, v) - plane.offset)
+            .fold(f32::MAX, f32::min),
+    }
+}
+
+// Distance from a point to a box body, 0 inside
+fn point_box_distance(point: [f32; 3], body: &Object, half: [f32; 3]) -> f32 {
+    let local = quat_rotate_inverse(body.orientation, sub(point, body.position));
+    length([
+        (local[0].abs() - half[0]).max(0.0),
+        (local[1].abs() - half[1]).max(0.0),
+        (local[2].abs() - half[2]).max(0.0),
+    ])
+}
+
+fn sphere_sphere(ca: [f32; 3], ra: f32, cb: [f32; 3], rb: f32) -> Option<Manifold> {
+    let delta = sub(cb, ca);
+    let distance = length(delta);
+    let penetration = ra + rb - distance;
+    if penetration <= 0.0 {
+        return None;
+    }
+    let normal = if distance > 0.0 { scale(delta, 1.0 / distance) } else { [0.0, 1.0, 0.0] };
+    let point = add(ca, scale(normal, ra - penetration * 0.5));
+    Some(Manifold::single(normal, point, penetration))
+}
+
+// Normal points from the sphere into the box
+fn sphere_box(center: [f32; 3], radius: f32, box_center: [f32; 3], box_orientation: [f32; 4], half: [f32; 3]) -> Option<Manifold> {
+    let local = quat_rotate_inverse(box_orientation, sub(center, box_center));
+    let mut closest = local;
+    for axis in 0..3 {
+        closest[axis] = closest[axis].max(-half[axis]).min(half[axis]);
+    }
+    let offset = sub(local, closest);
+    let distance_squared = dot(offset, offset);
+    if distance_squared > radius * radius {
+        return None;
+    }
+
+    let (normal, point, penetration) = if distance_squared > 0.0 {
+        let distance = distance_squared.sqrt();
+        (scale(offset, -1.0 / distance), closest, radius - distance)
+    } else {
+        // Center inside the box, push out through the nearest face
+        let mut axis = 0;
+        for i in 1..3 {
+            if half[i] - local[i].abs() < half[axis] - local[axis].abs() {
+                axis = i;
+            }
+        }
+        let side = if local[axis] < 0.0 { -1.0 } else { 1.0 };
+        let mut normal = [0.0; 3];
+        normal[axis] = -side;
+        let mut point = local;
+        point[axis] = side * half[axis];
+        (normal, point, radius + half[axis] - local[axis].abs())
+    };
+    let normal = quat_rotate(box_orientation, normal);
+    let point = add(box_center, quat_rotate(box_orientation, point));
+    Some(Manifold::single(normal, point, penetration))
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Feature {
+    FaceA(usize),
+    FaceB(usize),
+    Edges(usize, usize),
+}
+
+// Separating axis test over the 3 + 3 face normals and 9 edge cross products
+fn box_box(
+    ca: [f32; 3],
+    qa: [f32; 4],
+    ha: [f32; 3],
+    cb: [f32; 3],
+    qb: [f32; 4],
+    hb: [f32; 3],
+) -> Option<Manifold> {
+    let axes_a = box_axes(qa);
+    let axes_b = box_axes(qb);
+    let delta = sub(cb, ca);
+
+    let mut candidates = Vec::with_capacity(15);
+    for i in 0..3 {
+        candidates.push((axes_a[i], Feature::FaceA(i)));
+        candidates.push((axes_b[i], Feature::FaceB(i)));
+    }
+    for (i, &axis_a) in axes_a.iter().enumerate() {
+        for (j, &axis_b) in axes_b.iter().enumerate() {
+            candidates.push((cross(axis_a, axis_b), Feature::Edges(i, j)));
+        }
+    }
+
+    // Axis of least overlap as (score, overlap, normal, feature)
+    let mut best: Option<(f32, f32, [f32; 3], Feature)> = None;
+    for &(axis, feature) in candidates.iter() {
+        let axis_length = length(axis);
+        // Parallel edges give a degenerate axis already covered by the face axes
+        if axis_length < 1e-5 {
+            continue;
+        }
+        let axis = scale(axis, 1.0 / axis_length);
+        let distance = dot(delta, axis);
+        let overlap = project_box(&axes_a, ha, axis) + project_box(&axes_b, hb, axis) - distance.abs();
+        if overlap < 0.0 {
+            return None;
+        }
+        let score = match feature {
+            Feature::Edges(..) => overlap * EDGE_AXIS_BIAS + EDGE_AXIS_SLOP,
+            _ => overlap,
+        };
+        if best.map_or(true, |(best_score, ..)| score < best_score) {
+            let normal = if distance < 0.0 { scale(axis, -1.0) } else { axis };
+            best = Some((score, overlap, normal, feature));
+        }
+    }
+    let (_, overlap, normal, feature) = best?;
+
+    let contacts = match feature {
+        Feature::FaceA(k) => {
+            // Vertices of B below the face of A facing B
+            let face = dot(ca, normal) + ha[k];
+            face_contacts(&box_vertices(cb, qb, hb), normal, |v| face - dot(v, normal), ca, qa, ha, k, 0.5)
+        }
+        Feature::FaceB(k) => {
+            // Vertices of A above the face of B facing A
+            let face = dot(cb, normal) - hb[k];
+            face_contacts(&box_vertices(ca, qa, ha), normal, |v| dot(v, normal) - face, cb, qb, hb, k, -0.5)
+        }
+        Feature::Edges(i, j) => {
+            let (pa, pb) = closest_edge_points(
+                support_edge(ca, &axes_a, ha, i, normal),
+                axes_a[i],
+                ha[i],
+                support_edge(cb, &axes_b, hb, j, scale(normal, -1.0)),
+                axes_b[j],
+                hb[j],
+            );
+            vec![Contact { point: scale(add(pa, pb), 0.5), penetration: overlap }]
+        }
+    };
+    // Rounding can leave a face axis without any vertex inside, fall back to one central contact
+    Manifold::from_contacts(normal, contacts)
+        .or_else(|| Some(Manifold::single(normal, scale(add(ca, cb), 0.5), overlap)))
+}
+
+// Contacts for the vertices of the incident box that are behind the
+// reference face, clamped to the face so they stay on the reference box
+#[allow(clippy::too_many_arguments)]
+fn face_contacts<F: Fn([f32; 3]) -> f32>(
+    vertices: &[[f32; 3]; 8],
+    normal: [f32; 3],
+    depth: F,
+    reference_center: [f32; 3],
+    reference_orientation: [f32; 4],
+    reference_half: [f32; 3],
+    face_axis: usize,
+    midpoint_sign: f32,
+) -> Vec<Contact> {
+    vertices
+        .iter()
+        .filter_map(|&v| {
+            let penetration = depth(v);
+            if penetration <= 0.0 {
+                return None;
+            }
+            let mut local = quat_rotate_inverse(reference_orientation, sub(v, reference_center));
+            for axis in 0..3 {
+                if axis != face_axis {
+                    local[axis] = local[axis].max(-reference_half[axis]).min(reference_half[axis]);
+                }
+            }
+            let point = add(reference_center, quat_rotate(reference_orientation, local));
+            Some(Contact {
+                point: add(point, scale(normal, penetration * midpoint_sign)),
+                penetration,
+            })
+        })
+        .collect()
+}
+
+fn box_axes(orientation: [f32; 4]) -> [[f32; 3]; 3] {
+    [
+        quat_rotate(orientation, [1.0, 0.0, 0.0]),
+        quat_rotate(orientation, [0.0, 1.0, 0.0]),
+        quat_rotate(orientation, [0.0, 0.0, 1.0]),
+    ]
+}
+
+pub fn box_vertices(center: [f32; 3], orientation: [f32; 4], half: [f32; 3]) -> [[f32; 3]; 8] {
+    let mut vertices = [[0.0; 3]; 8];
+    for (corner, vertex) in vertices.iter_mut().enumerate() {
+        let local = [
+            if corner & 1 == 0 { -half[0] } else { half[0] },
+            if corner & 2 == 0 { -half[1] } else { half[1] },
+            if corner & 4 == 0 { -half[2] } else { half[2] },
+        ];
+        *vertex = add(center, quat_rotate(orientation, local));
+    }
+    vertices
+}
+
+// Half length of the box projected on a unit axis
+fn project_box(axes: &[[f32; 3]; 3], half: [f32; 3], axis: [f32; 3]) -> f32 {
+    (0..3).map(|i| half[i] * dot(axes[i], axis).abs()).sum()
+}
+
+// Midpoint of the edge parallel to axes[edge] that lies furthest along direction
+fn support_edge(center: [f32; 3], axes: &[[f32; 3]; 3], half: [f32; 3], edge: usize, direction: [f32; 3]) -> [f32; 3] {
+    let mut point = center;
+    for i in 0..3 {
+        if i != edge {
+            let side = if dot(axes[i], direction) < 0.0 { -half[i] } else { half[i] };
+            point = add(point, scale(axes[i], side));
+        }
+    }
+    point
+}
+
+// Closest points between segments p + s * d with |s| <= half length and unit d
+fn closest_edge_points(
+    pa: [f32; 3],
+    da: [f32; 3],
+    half_a: f32,
+    pb: [f32; 3],
+    db: [f32; 3],
+    half_b: f32,
+) -> ([f32; 3], [f32; 3]) {
+    let r = sub(pa, pb);
+    let b = dot(da, db);
+    let c = dot(da, r);
+    let f = dot(db, r);
+    let denominator = 1.0 - b * b;
+    let mut s = if denominator > 1e-6 { ((b * f - c) / denominator).max(-half_a).min(half_a) } else { 0.0 };
+    let t = (b * s + f).max(-half_b).min(half_b);
+    s = (b * t - c).max(-half_a).min(half_a);
+    (add(pa, scale(da, s)), add(pb, scale(db, t)))
+}
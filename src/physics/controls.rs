@@ -0,0 +1,130 @@
+use egui::{Slider, Ui};
+
+use crate::object::Object;
+
+use super::joint::Joint;
+use super::replay::Recorder;
+use super::world::PhysicsWorld;
+
+// Play, pause, step and reset of the simulation. The authored layout and
+// joints are snapshotted when the simulation first advances and restored on reset.
+#[derive(Clone)]
+pub struct SimulationControls {
+    pub is_playing: bool,
+    pub time_scale: f32,
+    pub snapshot: Option<(Vec<Object>, Vec<Joint>)>,
+    pub recorder: Recorder,
+    step_requested: bool,
+    reset_requested: bool,
+}
+
+impl SimulationControls {
+    pub fn new() -> Self {
+        Self {
+            is_playing: false,
+            time_scale: 1.0,
+            snapshot: None,
+            recorder: Recorder::new(),
+            step_requested: false,
+            reset_requested: false,
+        }
+    }
+
+    // Objects and joints as authored, which differ from the scene ones while a simulation is running
+    pub fn authored<'a>(&'a self, objects: &'a [Object], joints: &'a [Joint]) -> (&'a [Object], &'a [Joint]) {
+        match &self.snapshot {
+            Some((objects, joints)) => (objects, joints),
+            None => (objects, joints),
+        }
+    }
+
+    // Objects kept for reset and replay, for scene edits that must survive both
+    pub fn stored_objects_mut(&mut self) -> impl Iterator<Item = &mut Object> + '_ {
+        let snapshot = self.snapshot.iter_mut().flat_map(|(objects, _)| objects.iter_mut());
+        snapshot.chain(self.recorder.layout_objects_mut())
+    }
+
+    // Forgets the snapshot and the recording so the current objects become the authored layout
+    pub fn discard_snapshot(&mut self) {
+        self.is_playing = false;
+        self.snapshot = None;
+        self.recorder.clear();
+    }
+
+    // Applies the requested action and advances the world by the scaled frame time.
+    // Joints are written back too since bodies removed by the world take theirs along.
+    // Returns true on reset, for whatever is simulated next to the world to start over.
+    pub fn run(&mut self, world: &mut PhysicsWorld, frame_time: f32, objects: &mut Vec<Object>, joints: &mut Vec<Joint>) -> bool {
+        let is_reset = self.reset_requested;
+        if self.reset_requested {
+            if let Some(snapshot) = self.snapshot.take() {
+                (*objects, *joints) = snapshot;
+            }
+            self.is_playing = false;
+            // The recording belongs to the run being reset, the next one starts a new one
+            self.recorder.clear();
+            world.reset_clock();
+        }
+        let step = self.step_requested && !self.is_playing;
+        self.reset_requested = false;
+        self.step_requested = false;
+
+        // A frame on the timeline holds the simulation until it continues from there
+        if self.recorder.is_scrubbing() {
+            if !self.is_playing && !step {
+                self.recorder.replay(world, frame_time * self.time_scale, objects);
+                return is_reset;
+            }
+            self.recorder.resume();
+        }
+
+        if (self.is_playing || step) && self.snapshot.is_none() {
+            self.snapshot = Some((objects.clone(), joints.clone()));
+        }
+        world.joints.clone_from(joints);
+        if self.is_playing {
+            let recorder = &mut self.recorder;
+            world.update(frame_time * self.time_scale, objects, |world| recorder.record(world));
+        } else if step {
+            world.sync_from_scene(objects);
+            world.step(world.fixed_dt);
+            self.recorder.record(world);
+            world.clear_loads();
+            world.write_to_scene(objects);
+        }
+        joints.clone_from(&world.joints);
+        is_reset
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if ui.button(if self.is_playing { "⏸ Pause" } else { "▶ Play" }).clicked() {
+                self.is_playing = !self.is_playing;
+            }
+            if ui.add_enabled(!self.is_playing, egui::Button::new("Step")).clicked() {
+                self.step_requested = true;
+            }
+            if ui
+                .add_enabled(self.snapshot.is_some(), egui::Button::new("⟲ Reset"))
+                .on_hover_text("Restore the objects as they were before the simulation started")
+                .clicked()
+            {
+                self.reset_requested = true;
+            }
+            ui.add(Slider::new(&mut self.time_scale, 0.0..=4.0).text("Time Scale"));
+            self.recorder.ui(ui);
+        });
+    }
+
+    pub fn timeline_ui(&mut self, ui: &mut Ui) {
+        if self.recorder.timeline_ui(ui) {
+            self.is_playing = false;
+        }
+    }
+}
+
+impl Default for SimulationControls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,126 @@
+use egui::{Slider, Ui};
+
+use super::math::{add, cross, dot, length, normalize, scale, sub};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldKind {
+    // Constant push along the direction
+    Wind,
+    // Pull towards the center, a negative strength pushes away
+    Radial,
+    // Swirl around the direction through the center
+    Vortex,
+    // Slows bodies down in proportion to their speed
+    Drag,
+}
+
+impl FieldKind {
+    pub const ALL: [FieldKind; 4] = [FieldKind::Wind, FieldKind::Radial, FieldKind::Vortex, FieldKind::Drag];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldKind::Wind => "wind",
+            FieldKind::Radial => "radial",
+            FieldKind::Vortex => "vortex",
+            FieldKind::Drag => "drag",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FieldKind> {
+        FieldKind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    fn uses_direction(&self) -> bool {
+        matches!(self, FieldKind::Wind | FieldKind::Vortex)
+    }
+}
+
+// Acceleration zone acting on every awake dynamic body inside it, independent
+// of mass like gravity. Radial and vortex fields fade out towards the edge.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ForceField {
+    pub kind: FieldKind,
+    pub position: [f32; 3],
+    // Wind direction or vortex axis
+    pub direction: [f32; 3],
+    // Acceleration in m/s^2, or the drag rate per second
+    pub strength: f32,
+    // Radius of the zone around the position, 0 for the whole world
+    pub radius: f32,
+}
+
+impl ForceField {
+    pub fn new(kind: FieldKind) -> Self {
+        ForceField {
+            kind,
+            position: [0.0; 3],
+            direction: [0.0, 1.0, 0.0],
+            strength: 5.0,
+            radius: 10.0,
+        }
+    }
+
+    // Acceleration of a body at `position` moving with `velocity`
+    pub fn acceleration(&self, position: [f32; 3], velocity: [f32; 3]) -> [f32; 3] {
+        let offset = sub(position, self.position);
+        let distance = length(offset);
+        if self.radius > 0.0 && distance > self.radius {
+            return [0.0; 3];
+        }
+        let falloff = if self.radius > 0.0 { 1.0 - distance / self.radius } else { 1.0 };
+        match self.kind {
+            FieldKind::Wind => scale(normalize(self.direction), self.strength),
+            FieldKind::Radial => {
+                if distance < 1e-6 {
+                    return [0.0; 3];
+                }
+                scale(offset, -self.strength * falloff / distance)
+            }
+            FieldKind::Vortex => {
+                let axis = normalize(self.direction);
+                let radial = sub(offset, scale(axis, dot(offset, axis)));
+                if length(radial) < 1e-6 {
+                    return [0.0; 3];
+                }
+                scale(normalize(cross(axis, radial)), self.strength * falloff)
+            }
+            // Negative drag would speed bodies up without bound
+            FieldKind::Drag => scale(velocity, -self.strength.max(0.0)),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        egui::ComboBox::from_label("Kind")
+            .selected_text(self.kind.name())
+            .show_ui(ui, |ui| {
+                for kind in FieldKind::ALL.iter() {
+                    ui.selectable_value(&mut self.kind, *kind, kind.name());
+                }
+            });
+        ui.horizontal(|ui| {
+            ui.label("Center");
+            for value in self.position.iter_mut() {
+                ui.add(egui::DragValue::new(value).speed(0.1));
+            }
+        });
+        if self.kind.uses_direction() {
+            ui.horizontal(|ui| {
+                ui.label(if self.kind == FieldKind::Wind { "Direction" } else { "Axis" });
+                for value in self.direction.iter_mut() {
+                    ui.add(egui::DragValue::new(value).speed(0.01));
+                }
+            });
+        }
+        let min_strength = if self.kind == FieldKind::Drag { 0.0 } else { -50.0 };
+        ui.add(Slider::new(&mut self.strength, min_strength..=50.0).text("Strength"));
+        ui.add(Slider::new(&mut self.radius, 0.0..=200.0).text("Radius"))
+            .on_hover_text("0 makes the field act everywhere");
+    }
+}
+
+// Summed acceleration of all fields at a body
+pub fn field_acceleration(fields: &[ForceField], position: [f32; 3], velocity: [f32; 3]) -> [f32; 3] {
+    fields
+        .iter()
+        .fold([0.0; 3], |sum, field| add(sum, field.acceleration(position, velocity)))
+}
@@ -0,0 +1,164 @@
+use crate::object::Object;
+
+use super::collision::{collide, collide_plane, Manifold, Plane};
+use super::joint::{world_body, Joint, JointConstraint};
+use super::solver::{correct_positions, is_dynamic, pair_mut, ContactConstraint};
+
+// Velocity passes over all contacts of an island per step
+const SOLVER_ITERATIONS: usize = 8;
+
+// Awake bodies connected by contact candidates or joints. Islands share no dynamic
+// bodies, so each one is solved on its own copy and they can run in parallel.
+pub struct Island {
+    // World indices of the dynamic bodies, in the order of `bodies`
+    pub members: Vec<usize>,
+    // Copies of the members followed by the static or sleeping bodies they touch
+    pub bodies: Vec<Object>,
+    // Candidate pairs as indices into `bodies`, the first index is always the lower one
+    pub pairs: Vec<(usize, usize)>,
+    // Joints with the indices of their bodies in `bodies`
+    pub joints: Vec<(Joint, usize, usize)>,
+    // Set by solve when the island touches the ground, a static or sleeping body, or is
+    // jointed to one or to the world. Islands only held together by each other never sleep.
+    pub is_supported: bool,
+}
+
+impl Island {
+    // Sequential impulses over all joints and contacts, then a single positional correction
+    pub fn solve(&mut self, ground: Option<Plane>, resting_speed: f32, dt: f32) {
+        let mut manifolds: Vec<(usize, usize, Manifold)> = Vec::new();
+        for &(i, j) in self.pairs.iter() {
+            if let Some(manifold) = collide(&self.bodies[i], &self.bodies[j]) {
+                manifolds.push((i, j, manifold));
+            }
+        }
+        if let Some(plane) = ground {
+            // The plane joins the island as one more static body
+            let ground_index = self.bodies.len();
+            self.bodies.push(plane.as_body());
+            for i in 0..self.members.len() {
+                if let Some(manifold) = collide_plane(&self.bodies[i], &plane) {
+                    manifolds.push((i, ground_index, manifold));
+                }
+            }
+        }
+
+        // Everything past the members is static or asleep, the ground and the world included
+        let member_count = self.members.len();
+        self.is_supported = manifolds.iter().any(|&(_, j, _)| j >= member_count)
+            || self.joints.iter().any(|&(_, a, b)| a.max(b) >= member_count);
+
+        let joints: Vec<JointConstraint> = self
+            .joints
+            .iter()
+            .map(|(joint, a, b)| JointConstraint::new(&self.bodies, joint, *a, *b, dt))
+            .collect();
+        let mut constraints = Vec::new();
+        for (i, j, manifold) in manifolds.iter() {
+            for contact in manifold.contacts.iter() {
+                constraints.push(ContactConstraint::new(&self.bodies, *i, *j, manifold.normal, contact.point, resting_speed));
+            }
+        }
+        for _ in 0..SOLVER_ITERATIONS {
+            for joint in joints.iter() {
+                joint.solve(&mut self.bodies);
+            }
+            for constraint in constraints.iter_mut() {
+                constraint.solve(&mut self.bodies);
+            }
+        }
+        for (i, j, manifold) in manifolds.iter() {
+            let (a, b) = pair_mut(&mut self.bodies, *i, *j);
+            correct_positions(a, b, manifold);
+        }
+    }
+}
+
+// Groups the dynamic bodies by the candidate pairs and joints between them,
+// pairs and joints keep their order within an island. Joints must be valid.
+pub fn build_islands(bodies: &[Object], pairs: &[(usize, usize)], joints: &[Joint]) -> Vec<Island> {
+    let mut parents: Vec<usize> = (0..bodies.len()).collect();
+    let joint_pairs = joints.iter().filter_map(|joint| joint.body_b.map(|b| (joint.body_a, b)));
+    for (i, j) in pairs.iter().copied().chain(joint_pairs) {
+        if is_dynamic(&bodies[i]) && is_dynamic(&bodies[j]) {
+            let (root_i, root_j) = (find(&mut parents, i), find(&mut parents, j));
+            parents[root_i.max(root_j)] = root_i.min(root_j);
+        }
+    }
+
+    // Islands are numbered by their first member so the order is stable
+    let mut island_of = vec![usize::MAX; bodies.len()];
+    let mut islands: Vec<Island> = Vec::new();
+    for i in 0..bodies.len() {
+        if !is_dynamic(&bodies[i]) {
+            continue;
+        }
+        let root = find(&mut parents, i);
+        if island_of[root] == usize::MAX {
+            island_of[root] = islands.len();
+            islands.push(Island {
+                members: Vec::new(),
+                bodies: Vec::new(),
+                pairs: Vec::new(),
+                joints: Vec::new(),
+                is_supported: false,
+            });
+        }
+        island_of[i] = island_of[root];
+        let island = &mut islands[island_of[i]];
+        island.members.push(i);
+        island.bodies.push(bodies[i]);
+    }
+
+    // Local index of each member, static partners are appended per island as they are met
+    let mut local = vec![0; bodies.len()];
+    for island in islands.iter() {
+        for (l, &i) in island.members.iter().enumerate() {
+            local[i] = l;
+        }
+    }
+    for &(i, j) in pairs.iter() {
+        let (member, other) = match (is_dynamic(&bodies[i]), is_dynamic(&bodies[j])) {
+            (true, true) => {
+                islands[island_of[i]].pairs.push((local[i], local[j]));
+                continue;
+            }
+            (true, false) => (i, j),
+            (false, true) => (j, i),
+            (false, false) => continue,
+        };
+        let island = &mut islands[island_of[member]];
+        island.bodies.push(bodies[other]);
+        island.pairs.push((local[member], island.bodies.len() - 1));
+    }
+
+    // Non dynamic ends of a joint, and the world, join the island of the other end
+    for joint in joints.iter() {
+        let ends = [Some(joint.body_a), joint.body_b];
+        let member = match ends.iter().flatten().find(|&&i| is_dynamic(&bodies[i])) {
+            Some(&member) => member,
+            None => continue,
+        };
+        let island = &mut islands[island_of[member]];
+        let mut locals = [0; 2];
+        for (end, local_index) in ends.iter().zip(locals.iter_mut()) {
+            *local_index = match *end {
+                Some(i) if is_dynamic(&bodies[i]) => local[i],
+                other => {
+                    island.bodies.push(other.map_or_else(world_body, |i| bodies[i]));
+                    island.bodies.len() - 1
+                }
+            };
+        }
+        island.joints.push((*joint, locals[0], locals[1]));
+    }
+    islands
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
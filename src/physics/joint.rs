@@ -0,0 +1,365 @@
+use egui::{Slider, Ui};
+
+use crate::object::Object;
+
+use super::math::{add, cross, dot, length, normalize, quat_multiply, quat_rotate, quat_rotate_inverse, scale, sub};
+use super::solver::{pair_mut, tangent_basis, InverseMass};
+
+// Fraction of the joint drift fed back into the velocities per step
+const BAUMGARTE: f32 = 0.2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JointKind {
+    // Keeps the anchors at their initial distance, free to swing and spin
+    Distance,
+    // Pins the anchors together, free to rotate
+    BallSocket,
+    // Pins the anchors together, rotates only about the axis
+    Hinge,
+    // Keeps the orientation, slides only along the axis
+    Slider,
+    // Keeps the relative position and orientation
+    Fixed,
+}
+
+impl JointKind {
+    pub const ALL: [JointKind; 5] = [JointKind::Distance, JointKind::BallSocket, JointKind::Hinge, JointKind::Slider, JointKind::Fixed];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            JointKind::Distance => "distance",
+            JointKind::BallSocket => "ball",
+            JointKind::Hinge => "hinge",
+            JointKind::Slider => "slider",
+            JointKind::Fixed => "fixed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<JointKind> {
+        JointKind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    fn uses_axis(&self) -> bool {
+        matches!(self, JointKind::Hinge | JointKind::Slider)
+    }
+}
+
+// Constraint between two objects, or an object and the world. Anchors and
+// the axis are stored in the local frames of the bodies so they follow them,
+// the world acts as a static body at the origin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Joint {
+    pub kind: JointKind,
+    pub body_a: usize,
+    pub body_b: Option<usize>,
+    pub anchor_a: [f32; 3],
+    pub anchor_b: [f32; 3],
+    // Hinge or slider axis in the frame of body a
+    pub axis: [f32; 3],
+    // Rest distance between the anchors of a distance joint
+    pub length: f32,
+    // Orientation of body b relative to body a when the joint was made
+    pub reference: [f32; 4],
+}
+
+impl Joint {
+    // Joins the bodies at a world space pivot, in their current pose. Distance
+    // joints run from the center of a to the center of b, or to the pivot for the world.
+    pub fn new(kind: JointKind, bodies: &[Object], body_a: usize, body_b: Option<usize>, pivot: [f32; 3], axis: [f32; 3]) -> Self {
+        let a = &bodies[body_a];
+        let b = body_b.map_or_else(world_body, |b| bodies[b]);
+        let (anchor_a, anchor_b) = if kind == JointKind::Distance {
+            let anchor_b = if body_b.is_some() { [0.0; 3] } else { pivot };
+            ([0.0; 3], anchor_b)
+        } else {
+            (quat_rotate_inverse(a.orientation, sub(pivot, a.position)), quat_rotate_inverse(b.orientation, sub(pivot, b.position)))
+        };
+        let mut joint = Joint {
+            kind,
+            body_a,
+            body_b,
+            anchor_a,
+            anchor_b,
+            axis: quat_rotate_inverse(a.orientation, normalize(axis)),
+            length: 0.0,
+            reference: quat_multiply(conjugate(a.orientation), b.orientation),
+        };
+        let (pa, pb) = (joint.world_anchor(a, joint.anchor_a), joint.world_anchor(&b, joint.anchor_b));
+        joint.length = length(sub(pb, pa));
+        joint
+    }
+
+    fn world_anchor(&self, body: &Object, anchor: [f32; 3]) -> [f32; 3] {
+        add(body.position, quat_rotate(body.orientation, anchor))
+    }
+
+    // True when the joint refers to existing, distinct bodies
+    pub fn is_valid(&self, body_count: usize) -> bool {
+        self.body_a < body_count && self.body_b.map_or(true, |b| b < body_count && b != self.body_a)
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        let other = self.body_b.map_or("world".to_string(), |b| format!("object {}", b));
+        ui.label(format!("Object {} to {}", self.body_a, other));
+        if self.kind == JointKind::Distance {
+            ui.add(Slider::new(&mut self.length, 0.0..=50.0).text("Length"));
+        }
+        if self.kind.uses_axis() {
+            ui.horizontal(|ui| {
+                ui.label("Axis");
+                for value in self.axis.iter_mut() {
+                    ui.add(egui::DragValue::new(value).speed(0.01));
+                }
+            });
+        }
+    }
+}
+
+// Drops the joints of removed bodies and renumbers the rest, `keep` has one entry per body
+pub fn remove_bodies(joints: &mut Vec<Joint>, keep: &[bool]) {
+    let mut new_index = Vec::with_capacity(keep.len());
+    let mut count = 0;
+    for &kept in keep.iter() {
+        new_index.push(count);
+        count += kept as usize;
+    }
+    joints.retain(|joint| joint.is_valid(keep.len()) && keep[joint.body_a] && joint.body_b.map_or(true, |b| keep[b]));
+    for joint in joints.iter_mut() {
+        joint.body_a = new_index[joint.body_a];
+        joint.body_b = joint.body_b.map(|b| new_index[b]);
+    }
+}
+
+// Static stand-in for the world end of a joint
+pub fn world_body() -> Object {
+    let mut body = Object::new([0.0; 3], 0.0, [0.0; 3], 1.0, 0.0, true);
+    body.is_cube = false;
+    body
+}
+
+fn conjugate(q: [f32; 4]) -> [f32; 4] {
+    [q[0], -q[1], -q[2], -q[3]]
+}
+
+// One velocity row of a joint, either a direction the anchors may not move
+// apart along or an axis the bodies may not rotate about relative to each other
+struct Row {
+    is_angular: bool,
+    direction: [f32; 3],
+    mass: f32,
+    bias: f32,
+}
+
+// Joint prepared for one step of an island, bodies are indices into the island
+pub struct JointConstraint {
+    a: usize,
+    b: usize,
+    inverse_a: InverseMass,
+    inverse_b: InverseMass,
+    ra: [f32; 3],
+    rb: [f32; 3],
+    rows: Vec<Row>,
+}
+
+impl JointConstraint {
+    pub fn new(bodies: &[Object], joint: &Joint, a: usize, b: usize, dt: f32) -> Self {
+        let (body_a, body_b) = (&bodies[a], &bodies[b]);
+        let inverse_a = InverseMass::of(body_a);
+        let inverse_b = InverseMass::of(body_b);
+        let ra = quat_rotate(body_a.orientation, joint.anchor_a);
+        let rb = quat_rotate(body_b.orientation, joint.anchor_b);
+        let separation = sub(add(body_b.position, rb), add(body_a.position, ra));
+        let axis = normalize(quat_rotate(body_a.orientation, joint.axis));
+
+        // Position error along each constrained direction
+        let mut errors: Vec<(bool, [f32; 3], f32)> = Vec::new();
+        match joint.kind {
+            JointKind::Distance => {
+                let distance = length(separation);
+                if distance > 1e-6 {
+                    errors.push((false, scale(separation, 1.0 / distance), distance - joint.length));
+                }
+            }
+            JointKind::BallSocket | JointKind::Hinge | JointKind::Fixed => {
+                for k in 0..3 {
+                    let mut direction = [0.0; 3];
+                    direction[k] = 1.0;
+                    errors.push((false, direction, separation[k]));
+                }
+            }
+            JointKind::Slider => {
+                for tangent in tangent_basis(axis).iter() {
+                    errors.push((false, *tangent, dot(separation, *tangent)));
+                }
+            }
+        }
+        match joint.kind {
+            JointKind::Hinge => {
+                // Axis of a against the same axis carried by b, they differ by a small rotation
+                let axis_b = normalize(quat_rotate(body_b.orientation, quat_rotate_inverse(joint.reference, joint.axis)));
+                let twist = cross(axis, axis_b);
+                for tangent in tangent_basis(axis).iter() {
+                    errors.push((true, *tangent, dot(twist, *tangent)));
+                }
+            }
+            JointKind::Slider | JointKind::Fixed => {
+                // Rotation from the orientation b should have to the one it has, as a small angle vector
+                let target = quat_multiply(body_a.orientation, joint.reference);
+                let mut error = quat_multiply(body_b.orientation, conjugate(target));
+                if error[0] < 0.0 {
+                    error = scale4(error, -1.0);
+                }
+                for k in 0..3 {
+                    let mut direction = [0.0; 3];
+                    direction[k] = 1.0;
+                    errors.push((true, direction, 2.0 * error[k + 1]));
+                }
+            }
+            _ => {}
+        }
+
+        let rows = errors
+            .into_iter()
+            .filter_map(|(is_angular, direction, error)| {
+                let inverse_mass = if is_angular {
+                    dot(direction, inverse_a.angular(direction)) + dot(direction, inverse_b.angular(direction))
+                } else {
+                    inverse_a.along(ra, direction) + inverse_b.along(rb, direction)
+                };
+                if inverse_mass <= 0.0 {
+                    return None;
+                }
+                Some(Row {
+                    is_angular,
+                    direction,
+                    mass: 1.0 / inverse_mass,
+                    bias: BAUMGARTE * error / dt,
+                })
+            })
+            .collect();
+
+        JointConstraint {
+            a,
+            b,
+            inverse_a,
+            inverse_b,
+            ra,
+            rb,
+            rows,
+        }
+    }
+
+    // One solver iteration over the rows, the impulses aren't bounded
+    pub fn solve(&self, bodies: &mut [Object]) {
+        let (a, b) = pair_mut(bodies, self.a, self.b);
+        for row in self.rows.iter() {
+            let d = row.direction;
+            if row.is_angular {
+                let speed = dot(sub(b.angular_velocity, a.angular_velocity), d);
+                let impulse = scale(d, -(speed + row.bias) * row.mass);
+                a.angular_velocity = sub(a.angular_velocity, self.inverse_a.angular(impulse));
+                b.angular_velocity = add(b.angular_velocity, self.inverse_b.angular(impulse));
+            } else {
+                let velocity_a = add(a.velocity, cross(a.angular_velocity, self.ra));
+                let velocity_b = add(b.velocity, cross(b.angular_velocity, self.rb));
+                let speed = dot(sub(velocity_b, velocity_a), d);
+                let impulse = scale(d, -(speed + row.bias) * row.mass);
+                a.velocity = sub(a.velocity, scale(impulse, self.inverse_a.linear));
+                a.angular_velocity = sub(a.angular_velocity, self.inverse_a.angular(cross(self.ra, impulse)));
+                b.velocity = add(b.velocity, scale(impulse, self.inverse_b.linear));
+                b.angular_velocity = add(b.angular_velocity, self.inverse_b.angular(cross(self.rb, impulse)));
+            }
+        }
+    }
+}
+
+fn scale4(q: [f32; 4], s: f32) -> [f32; 4] {
+    [q[0] * s, q[1] * s, q[2] * s, q[3] * s]
+}
+
+// Settings for the next joint made from the UI
+#[derive(Clone)]
+pub struct JointTools {
+    pub kind: JointKind,
+    pub body_a: usize,
+    // Index of body b, -1 joins body a to the world
+    pub body_b: i32,
+    pub pivot: [f32; 3],
+    pub axis: [f32; 3],
+}
+
+impl JointTools {
+    pub fn new() -> Self {
+        Self {
+            kind: JointKind::BallSocket,
+            body_a: 0,
+            body_b: -1,
+            pivot: [0.0; 3],
+            axis: [0.0, 0.0, 1.0],
+        }
+    }
+
+    // Joint list with a remove button per joint, followed by the tools to add one
+    pub fn ui(&mut self, ui: &mut Ui, bodies: &[Object], joints: &mut Vec<Joint>) {
+        let mut remove_joint = None;
+        for (j, joint) in joints.iter_mut().enumerate() {
+            ui.push_id(("joint", j), |ui| {
+                ui.collapsing(format!("Joint {}: {}", j, joint.kind.name()), |ui| {
+                    joint.ui(ui);
+                    if ui.button("Remove Joint").clicked() {
+                        remove_joint = Some(j);
+                    }
+                });
+            });
+        }
+        if let Some(j) = remove_joint {
+            joints.remove(j);
+        }
+        if bodies.is_empty() {
+            ui.label("Add objects to join them");
+            return;
+        }
+
+        ui.separator();
+        egui::ComboBox::from_label("Kind")
+            .selected_text(self.kind.name())
+            .show_ui(ui, |ui| {
+                for kind in JointKind::ALL.iter() {
+                    ui.selectable_value(&mut self.kind, *kind, kind.name());
+                }
+            });
+        let last = bodies.len() - 1;
+        self.body_a = self.body_a.min(last);
+        self.body_b = self.body_b.min(last as i32);
+        ui.add(egui::DragValue::new(&mut self.body_a).clamp_range(0..=last).prefix("Object A: "));
+        ui.add(egui::DragValue::new(&mut self.body_b).clamp_range(-1..=last as i32).prefix("Object B (-1 world): "));
+        ui.horizontal(|ui| {
+            ui.label("Pivot");
+            for value in self.pivot.iter_mut() {
+                ui.add(egui::DragValue::new(value).speed(0.1));
+            }
+            if ui.button("At A").on_hover_text("Move the pivot to the center of object A").clicked() {
+                self.pivot = bodies[self.body_a].position;
+            }
+        });
+        if self.kind.uses_axis() {
+            ui.horizontal(|ui| {
+                ui.label("Axis");
+                for value in self.axis.iter_mut() {
+                    ui.add(egui::DragValue::new(value).speed(0.01));
+                }
+            });
+        }
+        let body_b = if self.body_b < 0 { None } else { Some(self.body_b as usize) };
+        let is_valid = body_b != Some(self.body_a) && length(self.axis) > 0.0;
+        if ui.add_enabled(is_valid, egui::Button::new("Add Joint")).clicked() {
+            joints.push(Joint::new(self.kind, bodies, self.body_a, body_b, self.pivot, self.axis));
+        }
+    }
+}
+
+impl Default for JointTools {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,92 @@
+// Small vector and quaternion helpers on plain arrays, matching the layout of
+// `Object`. Quaternions are stored w first.
+
+pub fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn length(a: [f32; 3]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+// Returns the zero vector for zero length input
+pub fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let l = length(a);
+    if l > 0.0 {
+        scale(a, 1.0 / l)
+    } else {
+        [0.0; 3]
+    }
+}
+
+// Scales `a` down so its length doesn't exceed `max`
+pub fn clamp_length(a: [f32; 3], max: f32) -> [f32; 3] {
+    let l = length(a);
+    if l > max {
+        scale(a, max / l)
+    } else {
+        a
+    }
+}
+
+pub fn quat_multiply(q1: [f32; 4], q2: [f32; 4]) -> [f32; 4] {
+    [
+        q1[0] * q2[0] - q1[1] * q2[1] - q1[2] * q2[2] - q1[3] * q2[3],
+        q1[0] * q2[1] + q1[1] * q2[0] + q1[2] * q2[3] - q1[3] * q2[2],
+        q1[0] * q2[2] - q1[1] * q2[3] + q1[2] * q2[0] + q1[3] * q2[1],
+        q1[0] * q2[3] + q1[1] * q2[2] - q1[2] * q2[1] + q1[3] * q2[0],
+    ]
+}
+
+pub fn quat_normalize(q: [f32; 4]) -> [f32; 4] {
+    let l = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    if l > 0.0 {
+        [q[0] / l, q[1] / l, q[2] / l, q[3] / l]
+    } else {
+        [1.0, 0.0, 0.0, 0.0]
+    }
+}
+
+// Rotates `v` by the unit quaternion `q`
+pub fn quat_rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let u = [q[1], q[2], q[3]];
+    let t = scale(cross(u, v), 2.0);
+    add(add(v, scale(t, q[0])), cross(u, t))
+}
+
+// Rotates `v` by the inverse of the unit quaternion `q`
+pub fn quat_rotate_inverse(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    quat_rotate([q[0], -q[1], -q[2], -q[3]], v)
+}
+
+// Advances an orientation by a world space angular velocity over `dt`
+pub fn integrate_orientation(q: [f32; 4], angular_velocity: [f32; 3], dt: f32) -> [f32; 4] {
+    let speed = length(angular_velocity);
+    if speed == 0.0 {
+        return q;
+    }
+    let axis = scale(angular_velocity, 1.0 / speed);
+    let (sin_half, cos_half) = (speed * dt * 0.5).sin_cos();
+    let delta = [cos_half, axis[0] * sin_half, axis[1] * sin_half, axis[2] * sin_half];
+    quat_normalize(quat_multiply(delta, q))
+}
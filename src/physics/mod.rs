@@ -0,0 +1,26 @@
+// Rigid body simulation of the scene objects, independent of rendering.
+// The world owns its own copy of the bodies, steps them at a fixed rate and
+// writes the result back to the scene once per frame.
+
+pub mod bench;
+mod broadphase;
+mod ccd;
+mod cloth;
+pub mod collision;
+mod controls;
+mod field;
+mod island;
+mod joint;
+pub mod math;
+pub mod query;
+mod replay;
+mod settings;
+mod solver;
+mod world;
+
+pub use cloth::{Cloth, ClothPins, GRID_SOURCE};
+pub use controls::SimulationControls;
+pub use field::{FieldKind, ForceField};
+pub use joint::{Joint, JointKind, JointTools};
+pub use settings::{BoundsAction, PhysicsSettings};
+pub use world::PhysicsWorld;
@@ -0,0 +1,276 @@
+// Ray, shape cast and overlap queries against a set of bodies, used by the
+// editor and by anything driving the simulation. Candidates come from a
+// bounds tree over the bodies, then are tested against their exact shape.
+// Queries starting inside a shape hit it at distance 0.
+
+use crate::bvh::{Aabb, Bvh};
+use crate::object::Object;
+
+use super::broadphase::overlaps;
+use super::ccd;
+use super::collision::{collide, Shape};
+use super::math::{add, dot, length, normalize, quat_rotate, quat_rotate_inverse, scale, sub};
+
+// Bounds tree over the bodies as they were when it was built, it has to be
+// rebuilt once they move
+#[derive(Clone, Debug, Default)]
+pub struct BodyTree {
+    bvh: Bvh,
+}
+
+impl BodyTree {
+    pub fn build(bodies: &[Object]) -> Self {
+        let bounds: Vec<Aabb> = bodies.iter().map(|b| Shape::of(b).bounds(b)).collect();
+        BodyTree { bvh: Bvh::build(&bounds) }
+    }
+
+    pub fn body_count(&self) -> usize {
+        self.bvh.order.len()
+    }
+
+    // Bodies in the leaves reached by descending into the nodes that pass `enter`, in ascending order
+    fn candidates(&self, enter: impl Fn(&Aabb) -> bool) -> Vec<usize> {
+        let mut candidates = Vec::new();
+        let mut stack = if self.bvh.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(node) = stack.pop() {
+            let node = &self.bvh.nodes[node];
+            if !enter(&Aabb { min: node.min, max: node.max }) {
+                continue;
+            }
+            let first = node.left_first as usize;
+            if node.count > 0 {
+                candidates.extend_from_slice(&self.bvh.order[first..first + node.count as usize]);
+            } else {
+                stack.push(first);
+                stack.push(first + 1);
+            }
+        }
+        // Ties between hits go to the lower index like a linear scan
+        candidates.sort_unstable();
+        candidates
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    // Index of the body that was hit
+    pub object: usize,
+    pub point: [f32; 3],
+    // Surface normal of the body at the hit, facing the query
+    pub normal: [f32; 3],
+    // Distance travelled along the query direction
+    pub distance: f32,
+}
+
+// Closest body along a ray, `direction` doesn't need to be normalized
+pub fn raycast(bodies: &[Object], tree: &BodyTree, origin: [f32; 3], direction: [f32; 3], max_distance: f32) -> Option<Hit> {
+    let direction = normalize(direction);
+    let candidates = tree.candidates(|bounds| ray_bounds(origin, direction, bounds.min, bounds.max).is_some_and(|t| t <= max_distance));
+    let mut closest: Option<Hit> = None;
+    for i in candidates {
+        let body = &bodies[i];
+        let hit = match Shape::of(body) {
+            Shape::Sphere { radius } => ray_sphere(origin, direction, body.position, radius),
+            Shape::Box { half_extents } => ray_box(origin, direction, body, half_extents),
+        };
+        if let Some((distance, normal)) = hit {
+            if distance <= max_distance && closest.map_or(true, |c| distance < c.distance) {
+                closest = Some(Hit {
+                    object: i,
+                    point: add(origin, scale(direction, distance)),
+                    normal,
+                    distance,
+                });
+            }
+        }
+    }
+    closest
+}
+
+// First body a sphere or box moving without rotation would touch
+pub fn shape_cast(
+    bodies: &[Object],
+    tree: &BodyTree,
+    shape: Shape,
+    orientation: [f32; 4],
+    origin: [f32; 3],
+    direction: [f32; 3],
+    max_distance: f32,
+) -> Option<Hit> {
+    let direction = normalize(direction);
+    let probe = probe(shape, origin, orientation);
+    let displacement = scale(direction, max_distance);
+    let swept = ccd::swept_bounds(&probe, displacement);
+
+    let mut closest: Option<(usize, f32)> = None;
+    for i in tree.candidates(|bounds| overlaps(&swept, bounds)) {
+        let body = &bodies[i];
+        let t = if collide(&probe, body).is_some() {
+            0.0
+        } else {
+            match ccd::time_of_impact(&probe, displacement, body) {
+                Some(t) => t,
+                None => continue,
+            }
+        };
+        if closest.map_or(true, |(_, closest_t)| t < closest_t) {
+            closest = Some((i, t));
+        }
+    }
+
+    let (object, t) = closest?;
+    let mut touching = probe;
+    touching.position = add(origin, scale(displacement, ccd::past_impact(displacement, t)));
+    // The contact just past the impact gives the point and the normal of the target
+    let (point, normal) = match collide(&touching, &bodies[object]) {
+        Some(manifold) => (manifold.contacts[0].point, scale(manifold.normal, -1.0)),
+        None => (touching.position, scale(direction, -1.0)),
+    };
+    Some(Hit {
+        object,
+        point,
+        normal,
+        distance: t * max_distance,
+    })
+}
+
+// Indices of the bodies a sphere or box placed at `position` overlaps
+pub fn overlap(bodies: &[Object], tree: &BodyTree, shape: Shape, position: [f32; 3], orientation: [f32; 4]) -> Vec<usize> {
+    let probe = probe(shape, position, orientation);
+    let bounds = shape.bounds(&probe);
+    tree.candidates(|node| overlaps(&bounds, node))
+        .into_iter()
+        .filter(|&i| overlaps(&bounds, &Shape::of(&bodies[i]).bounds(&bodies[i])) && collide(&probe, &bodies[i]).is_some())
+        .collect()
+}
+
+// Static body with the query shape, for the narrow phase
+fn probe(shape: Shape, position: [f32; 3], orientation: [f32; 4]) -> Object {
+    let mut body = Object::new(position, 0.0, [0.0; 3], 1.0, 0.0, true);
+    body.orientation = orientation;
+    match shape {
+        Shape::Sphere { radius } => {
+            body.is_cube = false;
+            body.radius = radius;
+        }
+        Shape::Box { half_extents } => {
+            body.is_cube = true;
+            body.size = scale(half_extents, 2.0);
+        }
+    }
+    body
+}
+
+// Entry distance of a ray into axis aligned bounds, slab by slab
+fn ray_bounds(origin: [f32; 3], direction: [f32; 3], min: [f32; 3], max: [f32; 3]) -> Option<f32> {
+    let (mut near, mut far) = (0.0f32, f32::MAX);
+    for axis in 0..3 {
+        if direction[axis].abs() < 1e-8 {
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let (t0, t1) = ((min[axis] - origin[axis]) / direction[axis], (max[axis] - origin[axis]) / direction[axis]);
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+        if near > far {
+            return None;
+        }
+    }
+    Some(near)
+}
+
+fn ray_sphere(origin: [f32; 3], direction: [f32; 3], center: [f32; 3], radius: f32) -> Option<(f32, [f32; 3])> {
+    let offset = sub(origin, center);
+    let c = dot(offset, offset) - radius * radius;
+    if c <= 0.0 {
+        return Some((0.0, scale(direction, -1.0)));
+    }
+    let b = dot(offset, direction);
+    let discriminant = b * b - c;
+    if b > 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    let distance = -b - discriminant.sqrt();
+    let normal = normalize(sub(add(origin, scale(direction, distance)), center));
+    Some((distance, normal))
+}
+
+// Slab test in the frame of the box, the normal is the face the ray enters through
+fn ray_box(origin: [f32; 3], direction: [f32; 3], body: &Object, half: [f32; 3]) -> Option<(f32, [f32; 3])> {
+    let local_origin = quat_rotate_inverse(body.orientation, sub(origin, body.position));
+    let local_direction = quat_rotate_inverse(body.orientation, direction);
+    let (mut near, mut far) = (f32::MIN, f32::MAX);
+    let mut normal = [0.0; 3];
+    for axis in 0..3 {
+        if local_direction[axis].abs() < 1e-8 {
+            if local_origin[axis].abs() > half[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (-half[axis] - local_origin[axis]) / local_direction[axis];
+        let t1 = (half[axis] - local_origin[axis]) / local_direction[axis];
+        if t0.min(t1) > near {
+            near = t0.min(t1);
+            normal = [0.0; 3];
+            normal[axis] = -local_direction[axis].signum();
+        }
+        far = far.min(t0.max(t1));
+    }
+    if near > far || far < 0.0 {
+        return None;
+    }
+    if near < 0.0 || length(normal) == 0.0 {
+        return Some((0.0, scale(direction, -1.0)));
+    }
+    Some((near, quat_rotate(body.orientation, normal)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box(position: [f32; 3]) -> Object {
+        Object::new(position, 0.0, [0.0; 3], 1.0, 0.0, true)
+    }
+
+    // A row of boxes along x, enough for the tree to split
+    fn row() -> Vec<Object> {
+        (0..32).map(|i| unit_box([i as f32 * 2.0, 0.0, 0.0])).collect()
+    }
+
+    #[test]
+    fn shape_cast_misses_a_nearby_box() {
+        let bodies = vec![unit_box([0.0; 3])];
+        let tree = BodyTree::build(&bodies);
+        // Passes over the top face with 0.02 clearance
+        let sphere = Shape::Sphere { radius: 0.5 };
+        let identity = [1.0, 0.0, 0.0, 0.0];
+        assert_eq!(shape_cast(&bodies, &tree, sphere, identity, [-5.0, 1.02, 0.0], [1.0, 0.0, 0.0], 10.0), None);
+        let hit = shape_cast(&bodies, &tree, sphere, identity, [-5.0, 0.9, 0.0], [1.0, 0.0, 0.0], 10.0).expect("cast should hit");
+        assert_eq!(hit.object, 0);
+    }
+
+    #[test]
+    fn raycast_finds_the_closest_body() {
+        let bodies = row();
+        let tree = BodyTree::build(&bodies);
+        let hit = raycast(&bodies, &tree, [20.0, 5.0, 0.0], [0.0, -1.0, 0.0], 100.0).expect("ray should hit");
+        assert_eq!(hit.object, 10);
+        assert!((hit.distance - 4.5).abs() < 1e-4);
+        assert_eq!(raycast(&bodies, &tree, [20.0, 5.0, 0.0], [0.0, -1.0, 0.0], 4.0), None);
+        // Along the row the first box wins
+        let hit = raycast(&bodies, &tree, [-5.0, 0.0, 0.0], [1.0, 0.0, 0.0], 100.0).expect("ray should hit");
+        assert_eq!(hit.object, 0);
+    }
+
+    #[test]
+    fn overlap_returns_the_touched_bodies() {
+        let bodies = row();
+        let tree = BodyTree::build(&bodies);
+        let touched = overlap(&bodies, &tree, Shape::Sphere { radius: 0.6 }, [41.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(touched, vec![20, 21]);
+    }
+}
@@ -0,0 +1,354 @@
+use egui::{Slider, Ui};
+
+use crate::object::Object;
+
+use super::world::PhysicsWorld;
+
+// Recording of the simulation state after every fixed step, for debugging.
+// A frame only holds the bodies whose state changed since the previous one,
+// with a full keyframe every KEYFRAME_INTERVAL steps and whenever bodies are
+// added, removed or edited, so resting piles cost next to nothing. States are
+// kept bit for bit: a replayed frame is the exact live state, and resuming
+// from it continues as the original run did given the same inputs.
+
+const KEYFRAME_INTERVAL: usize = 120;
+// Position, orientation, velocity, angular velocity, both accelerations,
+// sleep timer and sleeping flag
+const STATE_LEN: usize = 21;
+
+type State = [f32; STATE_LEN];
+
+#[derive(Clone)]
+struct Frame {
+    // Index into the layouts of the recording
+    layout: usize,
+    // Every body on keyframes, otherwise the ones that changed
+    changes: Vec<(u32, State)>,
+    is_keyframe: bool,
+}
+
+#[derive(Clone)]
+pub struct Recording {
+    // Bodies as they were when the set of bodies last changed, states are applied on top
+    layouts: Vec<Vec<Object>>,
+    frames: Vec<Frame>,
+    // States after the last frame, what the next one is compared against
+    last: Vec<State>,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Recording {
+            layouts: Vec::new(),
+            frames: Vec::new(),
+            last: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn record(&mut self, bodies: &[Object], sleep_timers: &[f32]) {
+        let states: Vec<State> = bodies.iter().zip(sleep_timers).map(|(body, &timer)| state_of(body, timer)).collect();
+        let layout_changed = self.layouts.last().map_or(true, |layout| {
+            layout.len() != bodies.len()
+                || layout.iter().zip(bodies).zip(&states).any(|((layout, body), state)| with_state(layout, state).0 != *body)
+        });
+        if layout_changed {
+            self.layouts.push(bodies.to_vec());
+        }
+
+        let is_keyframe = layout_changed || self.frames.len() % KEYFRAME_INTERVAL == 0;
+        let changes = states
+            .iter()
+            .enumerate()
+            .filter(|(i, state)| is_keyframe || !same_bits(state, &self.last[*i]))
+            .map(|(i, state)| (i as u32, *state))
+            .collect();
+        self.frames.push(Frame {
+            layout: self.layouts.len() - 1,
+            changes,
+            is_keyframe,
+        });
+        self.last = states;
+    }
+
+    // Bodies and their sleep timers after frame `index`, rebuilt from the keyframe before it
+    pub fn frame(&self, index: usize) -> (Vec<Object>, Vec<f32>) {
+        let states = self.states(index);
+        self.layouts[self.frames[index].layout]
+            .iter()
+            .zip(&states)
+            .map(|(layout, state)| with_state(layout, state))
+            .unzip()
+    }
+
+    fn states(&self, index: usize) -> Vec<State> {
+        let start = (0..=index).rev().find(|&i| self.frames[i].is_keyframe).unwrap_or(0);
+        let mut states = vec![[0.0; STATE_LEN]; self.layouts[self.frames[index].layout].len()];
+        for frame in self.frames[start..=index].iter() {
+            for &(i, state) in frame.changes.iter() {
+                states[i as usize] = state;
+            }
+        }
+        states
+    }
+
+    // Bodies of every layout, for scene edits that must reach the recorded frames too
+    pub fn layout_objects_mut(&mut self) -> impl Iterator<Item = &mut Object> + '_ {
+        self.layouts.iter_mut().flatten()
+    }
+
+    // Drops the frames after the first `len`, to record a different continuation
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.frames.len() {
+            return;
+        }
+        if len == 0 {
+            *self = Recording::new();
+            return;
+        }
+        self.frames.truncate(len);
+        self.layouts.truncate(self.frames[len - 1].layout + 1);
+        self.last = self.states(len - 1);
+    }
+}
+
+fn state_of(body: &Object, sleep_timer: f32) -> State {
+    let mut state = [0.0; STATE_LEN];
+    let flags = [sleep_timer, body.is_sleeping as i32 as f32];
+    let values = body
+        .position
+        .iter()
+        .chain(&body.orientation)
+        .chain(&body.velocity)
+        .chain(&body.angular_velocity)
+        .chain(&body.acceleration)
+        .chain(&body.angular_acceleration)
+        .chain(&flags);
+    for (slot, value) in state.iter_mut().zip(values) {
+        *slot = *value;
+    }
+    state
+}
+
+// The layout body moved into a recorded state, with its sleep timer
+fn with_state(layout: &Object, state: &State) -> (Object, f32) {
+    let mut body = *layout;
+    body.position.copy_from_slice(&state[0..3]);
+    body.orientation.copy_from_slice(&state[3..7]);
+    body.velocity.copy_from_slice(&state[7..10]);
+    body.angular_velocity.copy_from_slice(&state[10..13]);
+    body.acceleration.copy_from_slice(&state[13..16]);
+    body.angular_acceleration.copy_from_slice(&state[16..19]);
+    body.is_sleeping = state[20] != 0.0;
+    (body, state[19])
+}
+
+// Exact comparison, unlike == it tells 0 from -0
+fn same_bits(a: &State, b: &State) -> bool {
+    a.iter().zip(b).all(|(a, b)| a.to_bits() == b.to_bits())
+}
+
+// Records the live simulation and plays the recording back on a timeline.
+// While a frame is shown the simulation is held, continuing from there
+// drops the frames after it.
+#[derive(Clone)]
+pub struct Recorder {
+    pub is_recording: bool,
+    recording: Recording,
+    // Frame shown on the timeline, None while following the live simulation
+    cursor: Option<usize>,
+    // Frame last written to the scene
+    shown: Option<usize>,
+    is_replaying: bool,
+    // Playback time not yet consumed by a frame
+    accumulator: f32,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder {
+            is_recording: false,
+            recording: Recording::new(),
+            cursor: None,
+            shown: None,
+            is_replaying: false,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn has_frames(&self) -> bool {
+        !self.recording.is_empty()
+    }
+
+    pub fn is_scrubbing(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    pub fn layout_objects_mut(&mut self) -> impl Iterator<Item = &mut Object> + '_ {
+        self.recording.layout_objects_mut()
+    }
+
+    // Called after every fixed step of the live simulation
+    pub fn record(&mut self, world: &PhysicsWorld) {
+        if self.is_recording && self.cursor.is_none() {
+            self.recording.record(&world.bodies, world.sleep_timers());
+        }
+    }
+
+    // Advances the playback by the frame time and puts the frame on the timeline into the world and scene
+    pub fn replay(&mut self, world: &mut PhysicsWorld, frame_time: f32, objects: &mut Vec<Object>) {
+        let Some(mut cursor) = self.cursor else {
+            return;
+        };
+        if self.is_replaying {
+            self.accumulator += frame_time.max(0.0);
+            while self.accumulator >= world.fixed_dt && cursor + 1 < self.recording.len() {
+                self.accumulator -= world.fixed_dt;
+                cursor += 1;
+            }
+            if cursor + 1 >= self.recording.len() {
+                self.is_replaying = false;
+            }
+            self.cursor = Some(cursor);
+        }
+        if self.shown != Some(cursor) {
+            let (bodies, sleep_timers) = self.recording.frame(cursor);
+            world.restore(bodies, sleep_timers, objects);
+            self.shown = Some(cursor);
+        }
+    }
+
+    // Leaves the timeline so the simulation continues from the frame shown
+    pub fn resume(&mut self) {
+        if let Some(cursor) = self.cursor.take() {
+            if self.is_recording {
+                self.recording.truncate(cursor + 1);
+            }
+        }
+        self.shown = None;
+        self.is_replaying = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.recording = Recording::new();
+        self.cursor = None;
+        self.shown = None;
+        self.is_replaying = false;
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.toggle_value(&mut self.is_recording, "⏺ Record")
+            .on_hover_text("Record the state of every object after each step");
+    }
+
+    // Scrubbing and playback, returns true when the simulation has to be held for it
+    pub fn timeline_ui(&mut self, ui: &mut Ui) -> bool {
+        let last = self.recording.len().saturating_sub(1);
+        let mut hold = false;
+        ui.horizontal(|ui| {
+            let mut frame = self.cursor.unwrap_or(last);
+            if ui.button(if self.is_replaying { "⏸" } else { "▶" }).on_hover_text("Play the recording back").clicked() {
+                self.is_replaying = !self.is_replaying;
+                if self.is_replaying && frame >= last {
+                    frame = 0;
+                }
+                self.accumulator = 0.0;
+                self.cursor = Some(frame);
+                hold = true;
+            }
+            let response = ui.add(Slider::new(&mut frame, 0..=last).text(format!("of {} frames", self.recording.len())));
+            if response.changed() {
+                self.cursor = Some(frame);
+                self.is_replaying = false;
+                hold = true;
+            }
+            if ui
+                .add_enabled(self.cursor.is_some(), egui::Button::new("Live"))
+                .on_hover_text("Continue from the frame shown, later frames are dropped while recording")
+                .clicked()
+            {
+                self.resume();
+            }
+            if ui.button("Clear").clicked() {
+                self.clear();
+            }
+        });
+        hold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: usize = 240;
+
+    // Boxes dropped in a loose pile next to a few spheres, so several islands
+    // collide, spin and fall asleep during the run
+    fn pile() -> PhysicsWorld {
+        let mut objects = Vec::new();
+        for i in 0..12 {
+            let position = [(i % 4) as f32 * 0.9 - 1.3, 0.6 + (i / 4) as f32 * 1.1, (i % 3) as f32 * 0.3];
+            let mut body = Object::new(position, 0.5, [0.0; 3], 1.0, 0.0, false);
+            body.is_cube = i % 3 != 0;
+            body.velocity = [0.1 * (i % 5) as f32, 0.0, -0.2];
+            objects.push(body);
+        }
+        let mut world = PhysicsWorld::new();
+        world.sync_from_scene(&objects);
+        world
+    }
+
+    // Records the world after every step, advancing by `frame_time` until `STEPS` steps are in
+    fn run(mut world: PhysicsWorld, frame_time: f32) -> Recording {
+        let mut recording = Recording::new();
+        while recording.len() < STEPS {
+            world.advance_with(frame_time, |world| recording.record(&world.bodies, world.sleep_timers()));
+        }
+        recording.truncate(STEPS);
+        recording
+    }
+
+    // Whether frame `i` of `a` and frame `j` of `b` hold the same state bit for bit
+    fn same_frame(a: &Recording, i: usize, b: &Recording, j: usize) -> bool {
+        let ((a_bodies, a_timers), (b_bodies, b_timers)) = (a.frame(i), b.frame(j));
+        let a_states = a_bodies.iter().zip(&a_timers).map(|(body, &timer)| state_of(body, timer));
+        let b_states = b_bodies.iter().zip(&b_timers).map(|(body, &timer)| state_of(body, timer));
+        a_bodies.len() == b_bodies.len() && a_states.zip(b_states).all(|(a, b)| same_bits(&a, &b))
+    }
+
+    #[test]
+    fn frame_rate_does_not_change_the_run() {
+        let slow = run(pile(), 1.0 / 30.0);
+        let fast = run(pile(), 1.0 / 144.0);
+        for index in 0..STEPS {
+            assert!(same_frame(&slow, index, &fast, index), "frame {} differs", index);
+        }
+    }
+
+    #[test]
+    fn resuming_from_a_frame_repeats_the_run() {
+        let original = run(pile(), 1.0 / 60.0);
+        let k = 100;
+        let mut world = pile();
+        let (bodies, sleep_timers) = original.frame(k);
+        let mut objects = bodies.clone();
+        world.restore(bodies, sleep_timers, &mut objects);
+
+        let mut resumed = Recording::new();
+        for _ in k + 1..STEPS {
+            world.step(world.fixed_dt);
+            resumed.record(&world.bodies, world.sleep_timers());
+        }
+        for (index, resumed_index) in (k + 1..STEPS).zip(0..) {
+            assert!(same_frame(&original, index, &resumed, resumed_index), "frame {} differs after resuming from frame {}", index, k);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use egui::{Slider, Ui};
+
+use super::collision::Plane;
+
+// What happens to dynamic bodies that leave the world bounds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoundsAction {
+    Sleep,
+    Delete,
+}
+
+impl BoundsAction {
+    pub const ALL: [BoundsAction; 2] = [BoundsAction::Sleep, BoundsAction::Delete];
+
+    pub fn id(&self) -> i32 {
+        match self {
+            BoundsAction::Sleep => 0,
+            BoundsAction::Delete => 1,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BoundsAction::Sleep => "Sleep",
+            BoundsAction::Delete => "Delete",
+        }
+    }
+
+    pub fn from_id(id: i32) -> Option<BoundsAction> {
+        BoundsAction::ALL.iter().copied().find(|action| action.id() == id)
+    }
+}
+
+// Scene wide simulation parameters, saved with the scene
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhysicsSettings {
+    pub gravity: [f32; 3],
+    pub has_ground: bool,
+    pub ground_height: f32,
+    pub ground_restitution: f32,
+    pub ground_friction: f32,
+    pub has_bounds: bool,
+    pub bounds_min: [f32; 3],
+    pub bounds_max: [f32; 3],
+    pub bounds_action: BoundsAction,
+}
+
+impl Default for PhysicsSettings {
+    fn default() -> Self {
+        Self {
+            gravity: [0.0, -9.81, 0.0],
+            has_ground: false,
+            ground_height: 0.0,
+            ground_restitution: 0.3,
+            ground_friction: 0.5,
+            has_bounds: true,
+            bounds_min: [-100.0, -100.0, -100.0],
+            bounds_max: [100.0, 100.0, 100.0],
+            bounds_action: BoundsAction::Sleep,
+        }
+    }
+}
+
+impl PhysicsSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Horizontal plane at the ground height, facing up
+    pub fn ground_plane(&self) -> Option<Plane> {
+        if !self.has_ground {
+            return None;
+        }
+        let mut plane = Plane::new([0.0, 1.0, 0.0], self.ground_height);
+        plane.restitution = self.ground_restitution;
+        plane.friction = self.ground_friction;
+        Some(plane)
+    }
+
+    pub fn is_inside_bounds(&self, position: [f32; 3]) -> bool {
+        !self.has_bounds || (0..3).all(|axis| position[axis] >= self.bounds_min[axis] && position[axis] <= self.bounds_max[axis])
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.label("Gravity");
+        ui.add(Slider::new(&mut self.gravity[0], -30.0..=30.0).text("X"));
+        ui.add(Slider::new(&mut self.gravity[1], -30.0..=30.0).text("Y"));
+        ui.add(Slider::new(&mut self.gravity[2], -30.0..=30.0).text("Z"));
+        ui.add(egui::Checkbox::new(&mut self.has_ground, "Ground Plane"))
+            .on_hover_text("Infinite static floor, only collides and isn't rendered");
+        if self.has_ground {
+            ui.add(Slider::new(&mut self.ground_height, -100.0..=100.0).text("Ground Height"));
+            ui.add(Slider::new(&mut self.ground_restitution, 0.0..=1.0).text("Ground Restitution"));
+            ui.add(Slider::new(&mut self.ground_friction, 0.0..=2.0).text("Ground Friction"));
+        }
+        ui.add(egui::Checkbox::new(&mut self.has_bounds, "World Bounds"));
+        if self.has_bounds {
+            ui.horizontal(|ui| {
+                ui.label("Min");
+                for value in self.bounds_min.iter_mut() {
+                    ui.add(egui::DragValue::new(value).speed(1.0));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Max");
+                for value in self.bounds_max.iter_mut() {
+                    ui.add(egui::DragValue::new(value).speed(1.0));
+                }
+            });
+            egui::ComboBox::from_label("Escaped Bodies")
+                .selected_text(self.bounds_action.name())
+                .show_ui(ui, |ui| {
+                    for action in BoundsAction::ALL.iter() {
+                        ui.selectable_value(&mut self.bounds_action, *action, action.name());
+                    }
+                });
+        }
+        if ui.button("Reset").clicked() {
+            *self = PhysicsSettings::new();
+        }
+    }
+}
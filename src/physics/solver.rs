@@ -0,0 +1,253 @@
+// Sequential impulse contact solver shared by the world and its islands
+
+use crate::object::Object;
+
+use super::collision::{Manifold, Shape};
+use super::math::{add, cross, dot, normalize, quat_rotate, quat_rotate_inverse, scale, sub};
+
+// Overlap left unresolved and fraction of the rest corrected per step
+const PENETRATION_SLOP: f32 = 0.005;
+const CORRECTION_PERCENT: f32 = 0.8;
+
+// Bodies that integrate and respond to contacts, sleeping bodies act as static ones
+pub fn is_dynamic(body: &Object) -> bool {
+    !body.is_static && !body.is_sleeping
+}
+
+// Inverse mass and inertia tensor of a body, zero for static and sleeping bodies.
+// The tensor is diagonal along the body axes, so it is kept as the principal
+// moments and the orientation that turns them into world space.
+#[derive(Clone, Copy)]
+pub struct InverseMass {
+    pub linear: f32,
+    principal: [f32; 3],
+    orientation: [f32; 4],
+}
+
+impl InverseMass {
+    pub fn of(body: &Object) -> Self {
+        if !is_dynamic(body) {
+            return InverseMass {
+                linear: 0.0,
+                principal: [0.0; 3],
+                orientation: body.orientation,
+            };
+        }
+        let mass = body.mass.max(1e-6);
+        let inertia = Shape::of(body).inertia(mass);
+        InverseMass {
+            linear: 1.0 / mass,
+            principal: [1.0 / inertia[0].max(1e-6), 1.0 / inertia[1].max(1e-6), 1.0 / inertia[2].max(1e-6)],
+            orientation: body.orientation,
+        }
+    }
+
+    // Change of angular velocity caused by a world space torque impulse
+    pub fn angular(&self, torque: [f32; 3]) -> [f32; 3] {
+        let local = quat_rotate_inverse(self.orientation, torque);
+        let scaled = [local[0] * self.principal[0], local[1] * self.principal[1], local[2] * self.principal[2]];
+        quat_rotate(self.orientation, scaled)
+    }
+
+    // Inverse effective mass along `direction` for a contact at offset `r`
+    pub fn along(&self, r: [f32; 3], direction: [f32; 3]) -> f32 {
+        let rn = cross(r, direction);
+        self.linear + dot(rn, self.angular(rn))
+    }
+}
+
+fn point_velocity(body: &Object, r: [f32; 3]) -> [f32; 3] {
+    add(body.velocity, cross(body.angular_velocity, r))
+}
+
+fn apply_impulse(body: &mut Object, inverse: &InverseMass, r: [f32; 3], impulse: [f32; 3]) {
+    body.velocity = add(body.velocity, scale(impulse, inverse.linear));
+    body.angular_velocity = add(body.angular_velocity, inverse.angular(cross(r, impulse)));
+}
+
+// One contact point between bodies `a` < `b` of an island. Impulses are
+// accumulated over the solver iterations so the normal impulse stays pushing
+// and friction is bounded by the total normal impulse, not the last increment.
+pub struct ContactConstraint {
+    a: usize,
+    b: usize,
+    inverse_a: InverseMass,
+    inverse_b: InverseMass,
+    normal: [f32; 3],
+    tangents: [[f32; 3]; 2],
+    ra: [f32; 3],
+    rb: [f32; 3],
+    normal_mass: f32,
+    tangent_mass: [f32; 2],
+    friction: f32,
+    // Separating speed the normal impulse aims for, non zero when bouncing
+    target_speed: f32,
+    normal_impulse: f32,
+    tangent_impulse: [f32; 2],
+}
+
+impl ContactConstraint {
+    pub fn new(bodies: &[Object], a: usize, b: usize, normal: [f32; 3], point: [f32; 3], resting_speed: f32) -> Self {
+        let (body_a, body_b) = (&bodies[a], &bodies[b]);
+        let inverse_a = InverseMass::of(body_a);
+        let inverse_b = InverseMass::of(body_b);
+        let ra = sub(point, body_a.position);
+        let rb = sub(point, body_b.position);
+        let tangents = tangent_basis(normal);
+
+        // Slow contacts, like a body resting under gravity, don't bounce
+        let normal_speed = dot(sub(point_velocity(body_b, rb), point_velocity(body_a, ra)), normal);
+        let target_speed = if -normal_speed < resting_speed {
+            0.0
+        } else {
+            -normal_speed * body_a.restitution.max(body_b.restitution)
+        };
+
+        ContactConstraint {
+            a,
+            b,
+            normal,
+            tangents,
+            ra,
+            rb,
+            normal_mass: 1.0 / (inverse_a.along(ra, normal) + inverse_b.along(rb, normal)),
+            tangent_mass: [
+                1.0 / (inverse_a.along(ra, tangents[0]) + inverse_b.along(rb, tangents[0])),
+                1.0 / (inverse_a.along(ra, tangents[1]) + inverse_b.along(rb, tangents[1])),
+            ],
+            friction: (body_a.friction * body_b.friction).sqrt(),
+            target_speed,
+            inverse_a,
+            inverse_b,
+            normal_impulse: 0.0,
+            tangent_impulse: [0.0; 2],
+        }
+    }
+
+    // One solver iteration: friction bounded by the Coulomb cone, then the normal impulse
+    pub fn solve(&mut self, bodies: &mut [Object]) {
+        let (a, b) = pair_mut(bodies, self.a, self.b);
+
+        for t in 0..2 {
+            let tangent = self.tangents[t];
+            let relative = sub(point_velocity(b, self.rb), point_velocity(a, self.ra));
+            let max_impulse = self.friction * self.normal_impulse;
+            let total = (self.tangent_impulse[t] - dot(relative, tangent) * self.tangent_mass[t])
+                .max(-max_impulse)
+                .min(max_impulse);
+            let delta = total - self.tangent_impulse[t];
+            self.tangent_impulse[t] = total;
+            apply_impulse(a, &self.inverse_a, self.ra, scale(tangent, -delta));
+            apply_impulse(b, &self.inverse_b, self.rb, scale(tangent, delta));
+        }
+
+        let relative = sub(point_velocity(b, self.rb), point_velocity(a, self.ra));
+        let normal_speed = dot(relative, self.normal);
+        let total = (self.normal_impulse + (self.target_speed - normal_speed) * self.normal_mass).max(0.0);
+        let delta = total - self.normal_impulse;
+        self.normal_impulse = total;
+        apply_impulse(a, &self.inverse_a, self.ra, scale(self.normal, -delta));
+        apply_impulse(b, &self.inverse_b, self.rb, scale(self.normal, delta));
+    }
+}
+
+// Mutable references to two different bodies
+pub fn pair_mut(bodies: &mut [Object], i: usize, j: usize) -> (&mut Object, &mut Object) {
+    if i < j {
+        let (head, tail) = bodies.split_at_mut(j);
+        (&mut head[i], &mut tail[0])
+    } else {
+        let (head, tail) = bodies.split_at_mut(i);
+        (&mut tail[0], &mut head[j])
+    }
+}
+
+// Two unit vectors perpendicular to the normal and to each other
+pub fn tangent_basis(normal: [f32; 3]) -> [[f32; 3]; 2] {
+    let helper = if normal[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+    let first = normalize(cross(normal, helper));
+    [first, cross(normal, first)]
+}
+
+// Pushes the bodies apart along the manifold normal by the deepest penetration,
+// split by mass and leaving a little overlap so resting contacts persist
+pub fn correct_positions(a: &mut Object, b: &mut Object, manifold: &Manifold) {
+    let inverse_a = InverseMass::of(a);
+    let inverse_b = InverseMass::of(b);
+    let inverse_sum = inverse_a.linear + inverse_b.linear;
+    if inverse_sum == 0.0 {
+        return;
+    }
+    let correction = (manifold.max_penetration() - PENETRATION_SLOP).max(0.0) * CORRECTION_PERCENT / inverse_sum;
+    a.position = sub(a.position, scale(manifold.normal, correction * inverse_a.linear));
+    b.position = add(b.position, scale(manifold.normal, correction * inverse_b.linear));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::collision::collide;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn sphere(x: f32, velocity: f32, mass: f32, restitution: f32) -> Object {
+        let mut body = Object::new([x, 0.0, 0.0], 0.5, [0.0; 3], 1.0, 0.0, false);
+        body.is_cube = false;
+        body.velocity = [velocity, 0.0, 0.0];
+        body.mass = mass;
+        body.restitution = restitution;
+        body
+    }
+
+    // Slightly overlapping spheres closing along x, solved like an island does
+    fn head_on(a: Object, b: Object) -> [Object; 2] {
+        let mut bodies = [a, b];
+        let manifold = collide(&bodies[0], &bodies[1]).expect("spheres should touch");
+        let mut constraints: Vec<ContactConstraint> = manifold
+            .contacts
+            .iter()
+            .map(|contact| ContactConstraint::new(&bodies, 0, 1, manifold.normal, contact.point, 0.0))
+            .collect();
+        for _ in 0..8 {
+            for constraint in constraints.iter_mut() {
+                constraint.solve(&mut bodies);
+            }
+        }
+        bodies
+    }
+
+    fn momentum(bodies: &[Object]) -> [f32; 3] {
+        bodies.iter().fold([0.0; 3], |sum, body| add(sum, scale(body.velocity, body.mass)))
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!((0..3).all(|axis| (a[axis] - b[axis]).abs() < EPSILON), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn inelastic_equal_masses_keep_momentum() {
+        let before = [sphere(-0.49, 2.0, 1.0, 0.0), sphere(0.49, -1.0, 1.0, 0.0)];
+        let after = head_on(before[0], before[1]);
+        assert_close(momentum(&after), momentum(&before));
+        // Nothing bounces, they move on together
+        assert_close(after[0].velocity, after[1].velocity);
+    }
+
+    #[test]
+    fn elastic_equal_masses_swap_velocities() {
+        let before = [sphere(-0.49, 2.0, 1.0, 1.0), sphere(0.49, -1.0, 1.0, 1.0)];
+        let after = head_on(before[0], before[1]);
+        assert_close(momentum(&after), momentum(&before));
+        assert_close(after[0].velocity, before[1].velocity);
+        assert_close(after[1].velocity, before[0].velocity);
+    }
+
+    #[test]
+    fn unequal_masses_keep_momentum() {
+        for restitution in [0.0, 0.5, 1.0] {
+            let before = [sphere(-0.49, 3.0, 1.0, restitution), sphere(0.49, -1.0, 4.0, restitution)];
+            let after = head_on(before[0], before[1]);
+            assert_close(momentum(&after), momentum(&before));
+        }
+    }
+}
@@ -0,0 +1,515 @@
+use crate::bvh::Aabb;
+use crate::object::Object;
+
+use super::broadphase::SweepAndPrune;
+use super::ccd;
+use super::collision::{collide, Plane, Shape};
+use super::field::{field_acceleration, ForceField};
+use super::island::{build_islands, Island};
+use super::joint::{remove_bodies, Joint};
+use super::math::{add, clamp_length, cross, integrate_orientation, length, scale, sub};
+use super::query::{self, BodyTree, Hit};
+use super::settings::{BoundsAction, PhysicsSettings};
+use super::solver::{is_dynamic, InverseMass};
+
+const MAX_ANGULAR_SPEED: f32 = 10.0;
+// Frame times above this are clamped so a stall doesn't queue up hundreds of steps
+const MAX_FRAME_TIME: f32 = 0.25;
+// Approach speeds below this many steps of gravity are treated as resting
+const RESTING_SPEED_FACTOR: f32 = 2.0;
+// An island falls asleep once all its bodies stayed below these speeds for SLEEP_TIME seconds
+const SLEEP_LINEAR_SPEED: f32 = 0.25;
+const SLEEP_ANGULAR_SPEED: f32 = 0.25;
+const SLEEP_TIME: f32 = 0.5;
+// Fewer awake bodies than this are solved on the calling thread
+const PARALLEL_MIN_BODIES: usize = 256;
+// Speed given to a flung body per meter dragged
+const FLING_RATE: f32 = 4.0;
+
+// Force and torque applied to a body from outside the simulation
+#[derive(Clone, Copy, Default)]
+struct Load {
+    force: [f32; 3],
+    torque: [f32; 3],
+}
+
+pub struct PhysicsWorld {
+    pub bodies: Vec<Object>,
+    pub settings: PhysicsSettings,
+    // Joints between the bodies, invalid ones are ignored
+    pub joints: Vec<Joint>,
+    pub fields: Vec<ForceField>,
+    pub fixed_dt: f32,
+    broadphase: SweepAndPrune,
+    // Simulated time not yet consumed by a step, carried to the next frame
+    accumulator: f32,
+    // Bodies as last written to the scene, a difference means the scene was edited
+    written: Vec<Object>,
+    // Seconds each body has been slow enough to sleep
+    sleep_timers: Vec<f32>,
+    // Forces and torques held until the next advance has stepped
+    loads: Vec<Load>,
+    // Fields as of the last step, bodies asleep in a changed field wake up
+    stepped_fields: Vec<ForceField>,
+    // Bounds tree for the queries, rebuilt on the first query after the bodies
+    // stepped, were synced or restored
+    query_tree: BodyTree,
+    is_query_tree_stale: bool,
+}
+
+impl PhysicsWorld {
+    pub fn new() -> Self {
+        PhysicsWorld {
+            bodies: Vec::new(),
+            settings: PhysicsSettings::new(),
+            joints: Vec::new(),
+            fields: Vec::new(),
+            fixed_dt: 1.0 / 120.0,
+            broadphase: SweepAndPrune::new(),
+            accumulator: 0.0,
+            written: Vec::new(),
+            sleep_timers: Vec::new(),
+            loads: Vec::new(),
+            stepped_fields: Vec::new(),
+            query_tree: BodyTree::default(),
+            is_query_tree_stale: true,
+        }
+    }
+
+    // Takes over the scene objects if they were added, removed or edited since the last write back
+    pub fn sync_from_scene(&mut self, objects: &[Object]) {
+        if objects != self.written.as_slice() {
+            self.bodies = objects.to_vec();
+            self.written = self.bodies.clone();
+            self.sleep_timers = vec![0.0; self.bodies.len()];
+            self.loads = vec![Load::default(); self.bodies.len()];
+            self.is_query_tree_stale = true;
+        }
+    }
+
+    // Pushes body `index` with a world space force acting at a world space
+    // point, an off-center force also spins it. Held for every step of the
+    // next advance and dropped after it whether it stepped or not, call again
+    // each frame for a lasting push. The push then doesn't depend on the frame
+    // rate. Unknown bodies are ignored.
+    pub fn apply_force_at(&mut self, index: usize, force: [f32; 3], point: [f32; 3]) {
+        if index >= self.bodies.len() {
+            return;
+        }
+        self.fit_to_bodies();
+        let torque = cross(sub(point, self.bodies[index].position), force);
+        self.loads[index].force = add(self.loads[index].force, force);
+        self.apply_torque(index, torque);
+    }
+
+    // Changes the velocity of body `index` at once, as if struck at a world space point
+    pub fn apply_impulse_at(&mut self, index: usize, impulse: [f32; 3], point: [f32; 3]) {
+        if index >= self.bodies.len() {
+            return;
+        }
+        self.fit_to_bodies();
+        self.wake(index);
+        let body = &mut self.bodies[index];
+        let inverse = InverseMass::of(body);
+        body.velocity = add(body.velocity, scale(impulse, inverse.linear));
+        body.angular_velocity = add(body.angular_velocity, inverse.angular(cross(sub(point, body.position), impulse)));
+    }
+
+    // Throws scene object `index` so its point `from` heads towards `to`, for click and drag
+    pub fn fling(&mut self, objects: &mut Vec<Object>, index: usize, from: [f32; 3], to: [f32; 3]) {
+        self.sync_from_scene(objects);
+        if index >= self.bodies.len() {
+            return;
+        }
+        let mass = self.bodies[index].mass;
+        self.apply_impulse_at(index, scale(sub(to, from), FLING_RATE * mass), from);
+        self.write_to_scene(objects);
+    }
+
+    pub fn apply_torque(&mut self, index: usize, torque: [f32; 3]) {
+        if index >= self.bodies.len() {
+            return;
+        }
+        self.fit_to_bodies();
+        self.loads[index].torque = add(self.loads[index].torque, torque);
+        self.wake(index);
+    }
+
+    // Bodies can be pushed or removed directly, the state kept per body follows them
+    fn fit_to_bodies(&mut self) {
+        self.sleep_timers.resize(self.bodies.len(), 0.0);
+        self.loads.resize(self.bodies.len(), Load::default());
+    }
+
+    pub fn clear_loads(&mut self) {
+        self.loads.iter_mut().for_each(|load| *load = Load::default());
+    }
+
+    fn wake(&mut self, index: usize) {
+        if !self.bodies[index].is_static {
+            self.bodies[index].is_sleeping = false;
+            self.sleep_timers[index] = 0.0;
+        }
+    }
+
+    pub fn write_to_scene(&mut self, objects: &mut Vec<Object>) {
+        objects.clone_from(&self.bodies);
+        self.written.clone_from(&self.bodies);
+    }
+
+    pub fn sleep_timers(&self) -> &[f32] {
+        &self.sleep_timers
+    }
+
+    // Puts the bodies back into a recorded state and writes them to the scene
+    pub fn restore(&mut self, bodies: Vec<Object>, sleep_timers: Vec<f32>, objects: &mut Vec<Object>) {
+        self.loads = vec![Load::default(); bodies.len()];
+        self.bodies = bodies;
+        self.sleep_timers = sleep_timers;
+        self.stepped_fields.clone_from(&self.fields);
+        self.is_query_tree_stale = true;
+        self.accumulator = 0.0;
+        self.write_to_scene(objects);
+    }
+
+    pub fn raycast(&mut self, origin: [f32; 3], direction: [f32; 3], max_distance: f32) -> Option<Hit> {
+        self.refresh_query_tree();
+        query::raycast(&self.bodies, &self.query_tree, origin, direction, max_distance)
+    }
+
+    pub fn shape_cast(&mut self, shape: Shape, orientation: [f32; 4], origin: [f32; 3], direction: [f32; 3], max_distance: f32) -> Option<Hit> {
+        self.refresh_query_tree();
+        query::shape_cast(&self.bodies, &self.query_tree, shape, orientation, origin, direction, max_distance)
+    }
+
+    pub fn overlap(&mut self, shape: Shape, position: [f32; 3], orientation: [f32; 4]) -> Vec<usize> {
+        self.refresh_query_tree();
+        query::overlap(&self.bodies, &self.query_tree, shape, position, orientation)
+    }
+
+    fn refresh_query_tree(&mut self) {
+        if self.is_query_tree_stale || self.query_tree.body_count() != self.bodies.len() {
+            self.query_tree = BodyTree::build(&self.bodies);
+            self.is_query_tree_stale = false;
+        }
+    }
+
+    // Drops time accumulated but not yet simulated
+    pub fn reset_clock(&mut self) {
+        self.accumulator = 0.0;
+    }
+
+    // Runs as many fixed steps as fit in the accumulated time, returns the step count
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        self.advance_with(frame_time, |_| {})
+    }
+
+    // Like advance, calling `after_step` with the world after each step
+    pub fn advance_with(&mut self, frame_time: f32, mut after_step: impl FnMut(&PhysicsWorld)) -> u32 {
+        self.accumulator += frame_time.clamp(0.0, MAX_FRAME_TIME);
+        let mut steps = 0;
+        while self.accumulator >= self.fixed_dt {
+            self.step(self.fixed_dt);
+            after_step(self);
+            self.accumulator -= self.fixed_dt;
+            steps += 1;
+        }
+        self.clear_loads();
+        steps
+    }
+
+    // Pulls from the scene, advances and writes the result back
+    pub fn update(&mut self, frame_time: f32, objects: &mut Vec<Object>, after_step: impl FnMut(&PhysicsWorld)) {
+        self.sync_from_scene(objects);
+        if self.advance_with(frame_time, after_step) > 0 {
+            self.write_to_scene(objects);
+        }
+    }
+
+    // Depends only on the bodies, joints, fields, settings and applied loads,
+    // never on the frame time or on how islands are scheduled, so a run can
+    // be reproduced bit for bit
+    pub fn step(&mut self, dt: f32) {
+        self.fit_to_bodies();
+        self.wake_in_fields();
+        let fast = self.integrate(dt);
+        // Fast bodies enter the broad phase with the bounds of their whole
+        // motion, so the pairs hold both their sweep targets and their contacts
+        let mut bounds: Vec<Aabb> = self.bodies.iter().map(|b| Shape::of(b).bounds(b)).collect();
+        for &(i, start, displacement) in fast.iter() {
+            let mut body = self.bodies[i];
+            body.position = start;
+            bounds[i] = ccd::swept_bounds(&body, displacement);
+        }
+        let pairs = self.broadphase.pairs(&bounds);
+        self.sweep(&fast, &pairs);
+        let islands = self.resolve_contacts(pairs, dt);
+        self.update_sleep(&islands, dt);
+        self.enforce_bounds();
+        self.is_query_tree_stale = true;
+    }
+
+    // Fields and applied forces are accumulated into the acceleration of each
+    // body first, then velocities and positions advance with gravity added.
+    // Returns the bodies moving far enough to tunnel, with their start and displacement.
+    fn integrate(&mut self, dt: f32) -> Vec<(usize, [f32; 3], [f32; 3])> {
+        let gravity = self.settings.gravity;
+        let mut fast = Vec::new();
+        for (i, (body, load)) in self.bodies.iter_mut().zip(self.loads.iter()).enumerate().filter(|(_, (b, _))| is_dynamic(b)) {
+            let inverse = InverseMass::of(body);
+            body.acceleration = add(field_acceleration(&self.fields, body.position, body.velocity), scale(load.force, inverse.linear));
+            // Torques go through the world space inverse inertia of the current orientation
+            body.angular_acceleration = inverse.angular(load.torque);
+
+            body.velocity = add(body.velocity, scale(add(gravity, body.acceleration), dt));
+            if body.max_speed > 0.0 {
+                body.velocity = clamp_length(body.velocity, body.max_speed);
+            }
+            let displacement = scale(body.velocity, dt);
+            if ccd::needs_sweep(body, displacement) {
+                fast.push((i, body.position, displacement));
+            }
+            body.position = add(body.position, displacement);
+
+            body.angular_velocity = add(body.angular_velocity, scale(body.angular_acceleration, dt));
+            body.angular_velocity = scale(body.angular_velocity, 1.0 / (1.0 + body.angular_damping * dt));
+            body.angular_velocity = clamp_length(body.angular_velocity, MAX_ANGULAR_SPEED);
+            body.orientation = integrate_orientation(body.orientation, body.angular_velocity, dt);
+        }
+        fast
+    }
+
+    // Stops fast bodies at the first shape their inner sphere hits on the way,
+    // the contact is then resolved in the same step. Targets are the broad
+    // phase partners of the swept bounds, other bodies are taken at their new
+    // positions.
+    fn sweep(&mut self, fast: &[(usize, [f32; 3], [f32; 3])], pairs: &[(usize, usize)]) {
+        if fast.is_empty() {
+            return;
+        }
+        let mut slot = vec![usize::MAX; self.bodies.len()];
+        for (s, &(i, _, _)) in fast.iter().enumerate() {
+            slot[i] = s;
+        }
+        let mut targets: Vec<Vec<usize>> = vec![Vec::new(); fast.len()];
+        for &(i, j) in pairs.iter() {
+            if slot[i] != usize::MAX {
+                targets[slot[i]].push(j);
+            }
+            if slot[j] != usize::MAX {
+                targets[slot[j]].push(i);
+            }
+        }
+
+        let ground = self.settings.ground_plane();
+        for (&(i, start, displacement), targets) in fast.iter().zip(targets.iter()) {
+            let mut probe = ccd::inner_sphere(&self.bodies[i]);
+            probe.position = start;
+            let mut first = ground.and_then(|plane| ccd::time_of_impact_plane(&probe, displacement, &plane));
+            for &j in targets.iter() {
+                if let Some(t) = ccd::time_of_impact(&probe, displacement, &self.bodies[j]) {
+                    first = Some(first.map_or(t, |first: f32| first.min(t)));
+                }
+            }
+            if let Some(t) = first {
+                self.bodies[i].position = add(start, scale(displacement, ccd::past_impact(displacement, t)));
+            }
+        }
+    }
+
+    // Candidate pairs from the broad phase are split into islands of touching
+    // or jointed awake bodies, each solved with its pairs in index order so the result doesn't depend on how islands are
+    // scheduled. Jointed bodies don't collide with each other.
+    // Returns the members of every island and whether it is supported.
+    fn resolve_contacts(&mut self, mut pairs: Vec<(usize, usize)>, dt: f32) -> Vec<(Vec<usize>, bool)> {
+        let resting_speed = RESTING_SPEED_FACTOR * length(self.settings.gravity) * dt;
+        let joints: Vec<Joint> = self.joints.iter().copied().filter(|joint| joint.is_valid(self.bodies.len())).collect();
+        let mut jointed: Vec<(usize, usize)> = joints
+            .iter()
+            .filter_map(|joint| joint.body_b.map(|b| (joint.body_a.min(b), joint.body_a.max(b))))
+            .collect();
+        jointed.sort_unstable();
+        pairs.retain(|pair| jointed.binary_search(pair).is_err());
+        self.wake_touched(&pairs, &jointed);
+
+        let mut islands = build_islands(&self.bodies, &pairs, &joints);
+        solve_islands(&mut islands, self.settings.ground_plane(), resting_speed, dt);
+        islands
+            .into_iter()
+            .map(|island| {
+                for (local, &i) in island.members.iter().enumerate() {
+                    self.bodies[i] = island.bodies[local];
+                }
+                (island.members, island.is_supported)
+            })
+            .collect()
+    }
+
+    // A field added or edited over a settled scene only acts on awake bodies,
+    // so sleeping ones it pushes are woken first. Fields that stay as they
+    // are let bodies held against them fall asleep again.
+    fn wake_in_fields(&mut self) {
+        if self.fields == self.stepped_fields {
+            return;
+        }
+        self.stepped_fields.clone_from(&self.fields);
+        for i in 0..self.bodies.len() {
+            let body = &self.bodies[i];
+            if body.is_sleeping && !body.is_static && field_acceleration(&self.fields, body.position, body.velocity) != [0.0; 3] {
+                self.wake(i);
+            }
+        }
+    }
+
+    // Sleeping bodies in contact or jointed with an awake one wake up, a pile wakes one layer per step
+    fn wake_touched(&mut self, pairs: &[(usize, usize)], jointed: &[(usize, usize)]) {
+        for &(i, j) in jointed.iter() {
+            let (a, b) = (&self.bodies[i], &self.bodies[j]);
+            if is_dynamic(a) && b.is_sleeping {
+                self.wake(j);
+            } else if is_dynamic(b) && a.is_sleeping {
+                self.wake(i);
+            }
+        }
+        for &(i, j) in pairs.iter() {
+            let (a, b) = (&self.bodies[i], &self.bodies[j]);
+            let sleeper = if is_dynamic(a) && b.is_sleeping && !b.is_static {
+                j
+            } else if is_dynamic(b) && a.is_sleeping && !a.is_static {
+                i
+            } else {
+                continue;
+            };
+            if collide(a, b).is_some() {
+                self.wake(sleeper);
+            }
+        }
+    }
+
+    // Only islands resting on something count down, a body slowly falling or
+    // drifting on its own keeps moving however slow it is
+    fn update_sleep(&mut self, islands: &[(Vec<usize>, bool)], dt: f32) {
+        for (members, is_supported) in islands.iter() {
+            for &i in members.iter() {
+                let body = &self.bodies[i];
+                if *is_supported && length(body.velocity) < SLEEP_LINEAR_SPEED && length(body.angular_velocity) < SLEEP_ANGULAR_SPEED {
+                    self.sleep_timers[i] += dt;
+                } else {
+                    self.sleep_timers[i] = 0.0;
+                }
+            }
+            if members.iter().all(|&i| self.sleep_timers[i] >= SLEEP_TIME) {
+                for &i in members.iter() {
+                    let body = &mut self.bodies[i];
+                    body.is_sleeping = true;
+                    body.velocity = [0.0; 3];
+                    body.angular_velocity = [0.0; 3];
+                }
+            }
+        }
+    }
+
+    // Puts to sleep or removes dynamic bodies that left the world bounds
+    fn enforce_bounds(&mut self) {
+        let settings = self.settings;
+        match settings.bounds_action {
+            BoundsAction::Sleep => {
+                for body in self.bodies.iter_mut().filter(|b| is_dynamic(b)) {
+                    if !settings.is_inside_bounds(body.position) {
+                        body.is_sleeping = true;
+                        body.velocity = [0.0; 3];
+                        body.angular_velocity = [0.0; 3];
+                    }
+                }
+            }
+            BoundsAction::Delete => {
+                let keep: Vec<bool> = self.bodies.iter().map(|b| !is_dynamic(b) || settings.is_inside_bounds(b.position)).collect();
+                let mut kept = keep.iter();
+                self.bodies.retain(|_| *kept.next().unwrap());
+                let mut kept = keep.iter();
+                self.sleep_timers.retain(|_| *kept.next().unwrap());
+                let mut kept = keep.iter();
+                self.loads.retain(|_| *kept.next().unwrap());
+                remove_bodies(&mut self.joints, &keep);
+            }
+        }
+    }
+}
+
+impl Default for PhysicsWorld {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Spreads the islands over the available cores, balanced by body count
+fn solve_islands(islands: &mut [Island], ground: Option<Plane>, resting_speed: f32, dt: f32) {
+    let body_count: usize = islands.iter().map(|island| island.members.len()).sum();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(islands.len());
+    if body_count < PARALLEL_MIN_BODIES || threads < 2 {
+        for island in islands.iter_mut() {
+            island.solve(ground, resting_speed, dt);
+        }
+        return;
+    }
+
+    let mut groups: Vec<Vec<&mut Island>> = (0..threads).map(|_| Vec::new()).collect();
+    let mut loads = vec![0; threads];
+    for island in islands.iter_mut() {
+        let group = (0..threads).min_by_key(|&g| loads[g]).unwrap();
+        loads[group] += island.bodies.len();
+        groups[group].push(island);
+    }
+    std::thread::scope(|scope| {
+        for group in groups {
+            scope.spawn(move || {
+                for island in group {
+                    island.solve(ground, resting_speed, dt);
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two touching spheres side by side, drifting slowly along z
+    fn drifting_pair() -> PhysicsWorld {
+        let mut world = PhysicsWorld::new();
+        world.settings.ground_height = -0.5;
+        let bodies: Vec<Object> = [-0.49, 0.49]
+            .iter()
+            .map(|&x| {
+                let mut body = Object::new([x, 0.0, 0.0], 0.5, [0.0; 3], 1.0, 0.0, false);
+                body.is_cube = false;
+                body.velocity = [0.0, 0.0, 0.02];
+                body
+            })
+            .collect();
+        world.sync_from_scene(&bodies);
+        world
+    }
+
+    #[test]
+    fn touching_pair_in_free_flight_stays_awake() {
+        let mut world = drifting_pair();
+        world.settings.gravity = [0.0; 3];
+        world.settings.has_ground = false;
+        for _ in 0..600 {
+            world.step(world.fixed_dt);
+        }
+        assert!(world.bodies.iter().all(|body| !body.is_sleeping));
+    }
+
+    #[test]
+    fn touching_pair_on_the_ground_falls_asleep() {
+        let mut world = drifting_pair();
+        world.settings.has_ground = true;
+        for _ in 0..600 {
+            world.step(world.fixed_dt);
+        }
+        assert!(world.bodies.iter().all(|body| body.is_sleeping));
+    }
+}
@@ -0,0 +1,329 @@
+use std::ops::RangeInclusive;
+
+// Procedural texture node graphs.
+//
+// A graph is a flat list of nodes evaluated in order; the last node is the
+// graph output. Every node computes a pattern factor in [0, 1] and blends
+// between its two inputs, which are either an earlier node of the same graph
+// or the node's own constant color. The same evaluation is mirrored in
+// shaders/compute_shader.glsl (see `evaluate_texture`), so any change here has
+// to be made there as well.
+
+pub const MAX_TEXTURE_GRAPHS: usize = 8;
+pub const MAX_TEXTURE_NODES: usize = 32; // Total over all graphs, matches the shader
+
+// Material parameters a graph can be bound to (index into `Object::textures`)
+pub const TEXTURE_SLOT_COLOR: usize = 0;
+pub const TEXTURE_SLOT_ROUGHNESS: usize = 1;
+pub const TEXTURE_SLOT_EMISSION: usize = 2;
+pub const TEXTURE_SLOT_NAMES: [&str; 3] = ["Color", "Roughness", "Emission"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatternKind {
+    Checker,
+    Grid,
+    Noise,
+    Voronoi,
+    Gradient,
+    Brick,
+}
+
+impl PatternKind {
+    pub const ALL: [PatternKind; 6] = [
+        PatternKind::Checker,
+        PatternKind::Grid,
+        PatternKind::Noise,
+        PatternKind::Voronoi,
+        PatternKind::Gradient,
+        PatternKind::Brick,
+    ];
+
+    // Id used by the compute shader
+    pub fn id(self) -> i32 {
+        match self {
+            PatternKind::Checker => 0,
+            PatternKind::Grid => 1,
+            PatternKind::Noise => 2,
+            PatternKind::Voronoi => 3,
+            PatternKind::Gradient => 4,
+            PatternKind::Brick => 5,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PatternKind::Checker => "checker",
+            PatternKind::Grid => "grid",
+            PatternKind::Noise => "noise",
+            PatternKind::Voronoi => "voronoi",
+            PatternKind::Gradient => "gradient",
+            PatternKind::Brick => "brick",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PatternKind> {
+        PatternKind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    // What `TextureNode::param` means for this pattern
+    pub fn param_label(self) -> &'static str {
+        match self {
+            PatternKind::Checker => "Unused",
+            PatternKind::Grid => "Line Width",
+            PatternKind::Noise => "Octaves",
+            PatternKind::Voronoi => "Jitter",
+            PatternKind::Gradient => "Axis",
+            PatternKind::Brick => "Mortar Width",
+        }
+    }
+
+    pub fn param_range(self) -> RangeInclusive<f32> {
+        match self {
+            PatternKind::Checker => 0.0..=1.0,
+            PatternKind::Grid => 0.0..=0.5,
+            PatternKind::Noise => 1.0..=8.0,
+            PatternKind::Voronoi => 0.0..=1.0,
+            PatternKind::Gradient => 0.0..=2.0,
+            PatternKind::Brick => 0.0..=0.5,
+        }
+    }
+
+    pub fn default_param(self) -> f32 {
+        match self {
+            PatternKind::Checker => 0.0,
+            PatternKind::Grid => 0.05,
+            PatternKind::Noise => 4.0,
+            PatternKind::Voronoi => 1.0,
+            PatternKind::Gradient => 1.0,
+            PatternKind::Brick => 0.05,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureNode {
+    pub kind: PatternKind,
+    pub scale: f32,
+    pub param: f32,
+    pub color_a: [f32; 3], // 0..1, used when input_a is -1
+    pub color_b: [f32; 3], // 0..1, used when input_b is -1
+    pub input_a: i32, // Index of an earlier node in the same graph, or -1
+    pub input_b: i32,
+}
+
+impl TextureNode {
+    pub fn new(kind: PatternKind) -> Self {
+        TextureNode {
+            kind,
+            scale: 1.0,
+            param: kind.default_param(),
+            color_a: [0.1; 3],
+            color_b: [0.9; 3],
+            input_a: -1,
+            input_b: -1,
+        }
+    }
+
+    // Pattern factor in [0, 1] at `p`, before blending the inputs
+    pub fn factor(&self, p: [f32; 3]) -> f32 {
+        let p = [p[0] * self.scale, p[1] * self.scale, p[2] * self.scale];
+        match self.kind {
+            PatternKind::Checker => checker(p),
+            PatternKind::Grid => grid(p, self.param),
+            PatternKind::Noise => (fbm(p, self.param) * 0.5 + 0.5).clamp(0.0, 1.0),
+            PatternKind::Voronoi => voronoi(p, self.param).clamp(0.0, 1.0),
+            PatternKind::Gradient => {
+                let axis = (self.param as i32).clamp(0, 2) as usize;
+                (p[axis] * 0.5 + 0.5).clamp(0.0, 1.0)
+            }
+            PatternKind::Brick => brick(p, self.param),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureGraph {
+    pub name: String,
+    pub nodes: Vec<TextureNode>,
+}
+
+impl TextureGraph {
+    pub fn new(name: &str) -> Self {
+        TextureGraph {
+            name: name.to_string(),
+            nodes: vec![TextureNode::new(PatternKind::Checker)],
+        }
+    }
+
+    // Color of the graph output at local position `p`
+    pub fn evaluate(&self, p: [f32; 3]) -> [f32; 3] {
+        let mut values: Vec<[f32; 3]> = Vec::with_capacity(self.nodes.len());
+        for (i, node) in self.nodes.iter().enumerate() {
+            let a = if node.input_a >= 0 && (node.input_a as usize) < i { values[node.input_a as usize] } else { node.color_a };
+            let b = if node.input_b >= 0 && (node.input_b as usize) < i { values[node.input_b as usize] } else { node.color_b };
+            let t = node.factor(p);
+            values.push([
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]);
+        }
+        values.last().copied().unwrap_or([1.0; 3])
+    }
+}
+
+// Scalar material parameters are scaled by the luminance of the graph output
+pub fn luminance(color: [f32; 3]) -> f32 {
+    color[0] * 0.2126 + color[1] * 0.7152 + color[2] * 0.0722
+}
+
+// Packs the graphs into the flat arrays uploaded to the compute shader.
+// Returns the nodes and a (first node, node count) range per graph. Graphs
+// that don't fit in the shader limits are dropped.
+pub fn flatten_graphs(graphs: &[TextureGraph]) -> (Vec<TextureNode>, Vec<[i32; 2]>) {
+    let mut nodes = Vec::new();
+    let mut ranges = Vec::new();
+    for graph in graphs.iter().take(MAX_TEXTURE_GRAPHS) {
+        if graph.nodes.is_empty() || nodes.len() + graph.nodes.len() > MAX_TEXTURE_NODES {
+            ranges.push([0, 0]);
+            continue;
+        }
+        ranges.push([nodes.len() as i32, graph.nodes.len() as i32]);
+        for (i, node) in graph.nodes.iter().enumerate() {
+            let mut node = *node;
+            // Inputs must reference earlier nodes, anything else falls back to the constant color
+            if node.input_a >= i as i32 {
+                node.input_a = -1;
+            }
+            if node.input_b >= i as i32 {
+                node.input_b = -1;
+            }
+            nodes.push(node);
+        }
+    }
+    (nodes, ranges)
+}
+
+fn checker(p: [f32; 3]) -> f32 {
+    let sum = p[0].floor() + p[1].floor() + p[2].floor();
+    if sum.rem_euclid(2.0) < 1.0 { 0.0 } else { 1.0 }
+}
+
+fn grid(p: [f32; 3], width: f32) -> f32 {
+    for c in p.iter() {
+        let f = c - c.floor();
+        if f < width || f > 1.0 - width {
+            return 1.0;
+        }
+    }
+    0.0
+}
+
+fn brick(p: [f32; 3], mortar: f32) -> f32 {
+    // Bricks are twice as wide as they are tall, every other row is offset by half a brick
+    let v = p[1];
+    let row = v.floor();
+    let mut u = (p[0] + p[2]) * 0.5;
+    if row.rem_euclid(2.0) >= 1.0 {
+        u += 0.5;
+    }
+    let fu = u - u.floor();
+    let fv = v - row;
+    if fu < mortar * 0.5 || fu > 1.0 - mortar * 0.5 || fv < mortar || fv > 1.0 - mortar {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn hash3(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    h
+}
+
+fn grad(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// Classic Perlin gradient noise, roughly in [-1, 1]
+fn perlin(p: [f32; 3]) -> f32 {
+    let cell = [p[0].floor(), p[1].floor(), p[2].floor()];
+    let (ix, iy, iz) = (cell[0] as i32, cell[1] as i32, cell[2] as i32);
+    let (x, y, z) = (p[0] - cell[0], p[1] - cell[1], p[2] - cell[2]);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let n000 = grad(hash3(ix, iy, iz), x, y, z);
+    let n100 = grad(hash3(ix + 1, iy, iz), x - 1.0, y, z);
+    let n010 = grad(hash3(ix, iy + 1, iz), x, y - 1.0, z);
+    let n110 = grad(hash3(ix + 1, iy + 1, iz), x - 1.0, y - 1.0, z);
+    let n001 = grad(hash3(ix, iy, iz + 1), x, y, z - 1.0);
+    let n101 = grad(hash3(ix + 1, iy, iz + 1), x - 1.0, y, z - 1.0);
+    let n011 = grad(hash3(ix, iy + 1, iz + 1), x, y - 1.0, z - 1.0);
+    let n111 = grad(hash3(ix + 1, iy + 1, iz + 1), x - 1.0, y - 1.0, z - 1.0);
+
+    lerp(
+        lerp(lerp(n000, n100, u), lerp(n010, n110, u), v),
+        lerp(lerp(n001, n101, u), lerp(n011, n111, u), v),
+        w,
+    )
+}
+
+fn fbm(p: [f32; 3], octaves: f32) -> f32 {
+    let octaves = (octaves as i32).clamp(1, 8);
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves {
+        sum += amplitude * perlin([p[0] * frequency, p[1] * frequency, p[2] * frequency]);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+// Distance to the closest feature point (F1)
+fn voronoi(p: [f32; 3], jitter: f32) -> f32 {
+    let cell = [p[0].floor(), p[1].floor(), p[2].floor()];
+    let mut closest = 8.0f32;
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let c = [cell[0] as i32 + dx, cell[1] as i32 + dy, cell[2] as i32 + dz];
+                let h = hash3(c[0], c[1], c[2]);
+                let offset = [
+                    (h & 0x3ff) as f32 / 1023.0,
+                    ((h >> 10) & 0x3ff) as f32 / 1023.0,
+                    ((h >> 20) & 0x3ff) as f32 / 1023.0,
+                ];
+                let feature = [
+                    c[0] as f32 + 0.5 + (offset[0] - 0.5) * jitter,
+                    c[1] as f32 + 0.5 + (offset[1] - 0.5) * jitter,
+                    c[2] as f32 + 0.5 + (offset[2] - 0.5) * jitter,
+                ];
+                let d = [feature[0] - p[0], feature[1] - p[1], feature[2] - p[2]];
+                closest = closest.min((d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt());
+            }
+        }
+    }
+    closest
+}
@@ -0,0 +1,150 @@
+// Owen scrambled Sobol sequence, mirrored by `sobolSample` in compute_shader.glsl.
+//
+// Each pixel gets its own seed, the sample index is shuffled and every
+// dimension is scrambled independently (Burley 2020, "Practical Hash-based
+// Owen Scrambling"). The first SOBOL_DIMENSIONS values of a path come from
+// the sequence, everything after that falls back to PCG seeded by the sample.
+
+pub const SOBOL_DIMENSIONS: usize = 4;
+
+// Direction numbers for dimensions 1..4, dimension 0 is the bit reversed index
+const SOBOL_DIRECTIONS: [[u32; 32]; SOBOL_DIMENSIONS - 1] = [
+    [
+        0x80000000, 0xc0000000, 0xa0000000, 0xf0000000, 0x88000000, 0xcc000000, 0xaa000000, 0xff000000,
+        0x80800000, 0xc0c00000, 0xa0a00000, 0xf0f00000, 0x88880000, 0xcccc0000, 0xaaaa0000, 0xffff0000,
+        0x80008000, 0xc000c000, 0xa000a000, 0xf000f000, 0x88008800, 0xcc00cc00, 0xaa00aa00, 0xff00ff00,
+        0x80808080, 0xc0c0c0c0, 0xa0a0a0a0, 0xf0f0f0f0, 0x88888888, 0xcccccccc, 0xaaaaaaaa, 0xffffffff,
+    ],
+    [
+        0x80000000, 0xc0000000, 0x60000000, 0x90000000, 0xe8000000, 0x5c000000, 0x8e000000, 0xc5000000,
+        0x68800000, 0x9cc00000, 0xee600000, 0x55900000, 0x80680000, 0xc09c0000, 0x60ee0000, 0x90550000,
+        0xe8808000, 0x5cc0c000, 0x8e606000, 0xc5909000, 0x6868e800, 0x9c9c5c00, 0xeeee8e00, 0x5555c500,
+        0x8000e880, 0xc0005cc0, 0x60008e60, 0x9000c590, 0xe8006868, 0x5c009c9c, 0x8e00eeee, 0xc5005555,
+    ],
+    [
+        0x80000000, 0xc0000000, 0x20000000, 0x50000000, 0xf8000000, 0x74000000, 0xa2000000, 0x93000000,
+        0xd8800000, 0x25400000, 0x59e00000, 0xe6d00000, 0x78080000, 0xb40c0000, 0x82020000, 0xc3050000,
+        0x208f8000, 0x51474000, 0xfbea2000, 0x75d93000, 0xa0858800, 0x914e5400, 0xdbe79e00, 0x25db6d00,
+        0x58800080, 0xe54000c0, 0x79e00020, 0xb6d00050, 0x800800f8, 0xc00c0074, 0x200200a2, 0x50050093,
+    ],
+];
+
+pub struct Sampler {
+    seed: u32,
+}
+
+impl Sampler {
+    // One sampler per pixel, the seed decorrelates neighbouring pixels
+    pub fn new(x: u32, y: u32) -> Self {
+        Sampler { seed: pcg_hash(x ^ pcg_hash(y)) }
+    }
+
+    // Returns the first SOBOL_DIMENSIONS values of sample `index` in [0,1)
+    pub fn sample(&self, index: u32) -> [f32; SOBOL_DIMENSIONS] {
+        let index = nested_uniform_scramble(index, self.seed);
+        let mut values = [0.0; SOBOL_DIMENSIONS];
+        for (dim, value) in values.iter_mut().enumerate() {
+            let x = nested_uniform_scramble(sobol(index, dim), hash_combine(self.seed, dim as u32));
+            *value = (x >> 8) as f32 / (1u32 << 24) as f32;
+        }
+        values
+    }
+}
+
+pub fn sobol(index: u32, dim: usize) -> u32 {
+    if dim == 0 {
+        return index.reverse_bits();
+    }
+    let mut x = 0;
+    for (bit, direction) in SOBOL_DIRECTIONS[dim - 1].iter().enumerate() {
+        if (index >> bit) & 1 != 0 {
+            x ^= direction;
+        }
+    }
+    x
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+pub fn hash_combine(seed: u32, value: u32) -> u32 {
+    seed ^ (value
+        .wrapping_add(0x9e3779b9)
+        .wrapping_add(seed << 6)
+        .wrapping_add(seed >> 2))
+}
+
+pub fn pcg_hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXELS: u32 = 16;
+
+    fn to_unit(x: u32) -> f32 {
+        (x >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    // Error of every pixel of a 16x16 image of a constant integral against its
+    // exact value, the points of pixel (x, y) come from `points`
+    fn rmse(f: impl Fn(f32, f32) -> f32, reference: f32, samples: u32, points: impl Fn(u32, u32, u32) -> [f32; 2]) -> f32 {
+        let mut squared = 0.0;
+        for y in 0..PIXELS {
+            for x in 0..PIXELS {
+                let sum: f32 = (0..samples).map(|i| {
+                    let [u, v] = points(x, y, i);
+                    f(u, v)
+                }).sum();
+                squared += (sum / samples as f32 - reference).powi(2);
+            }
+        }
+        (squared / (PIXELS * PIXELS) as f32).sqrt()
+    }
+
+    fn sobol_points(x: u32, y: u32, i: u32) -> [f32; 2] {
+        let values = Sampler::new(x, y).sample(i);
+        [values[0], values[1]]
+    }
+
+    // Chained hashes like the shader's PCG fallback
+    fn pcg_points(x: u32, y: u32, i: u32) -> [f32; 2] {
+        let state = pcg_hash(hash_combine(pcg_hash(x ^ pcg_hash(y)), i));
+        let next = pcg_hash(state);
+        [to_unit(state), to_unit(next)]
+    }
+
+    fn assert_converges_faster(f: impl Fn(f32, f32) -> f32 + Copy, reference: f32) {
+        for samples in [64, 256, 1024] {
+            let sobol = rmse(f, reference, samples, sobol_points);
+            let pcg = rmse(f, reference, samples, pcg_points);
+            assert!(sobol < pcg, "{} samples: Sobol RMSE {} not below PCG {}", samples, sobol, pcg);
+        }
+    }
+
+    #[test]
+    fn sobol_beats_pcg_on_a_smooth_bump() {
+        let pi = std::f32::consts::PI;
+        assert_converges_faster(|u, v| (pi * u).sin() * (pi * v).sin(), 4.0 / (pi * pi));
+    }
+
+    #[test]
+    fn sobol_beats_pcg_on_an_edge() {
+        // Half the square lies below the diagonal, like a pixel straddling an edge
+        assert_converges_faster(|u, v| if u + v < 1.0 { 1.0 } else { 0.0 }, 0.5);
+    }
+}
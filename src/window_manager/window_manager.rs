@@ -2,6 +2,178 @@ pub mod windows{
     use egui::{Modifiers, Slider, Ui};
 
     use crate::Object;
+    use crate::mesh::{instance_grid, instance_scatter, Instance, Mesh, BUILTIN_MESHES};
+    use crate::particles::{ParticleEmitter, ParticleSystem};
+    use crate::physics::query::Hit;
+    use crate::physics::{Cloth, FieldKind, ForceField, Joint, JointTools, PhysicsSettings, PhysicsWorld, SimulationControls, GRID_SOURCE};
+    use crate::procedural::{PatternKind, TextureGraph, TextureNode, MAX_TEXTURE_GRAPHS, TEXTURE_SLOT_NAMES};
+    use crate::sampler::Sampler;
+    use crate::scene::{load_scene, save_scene};
+
+    // What the fullscreen quad shows, see quad_fragment_shader.glsl
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum DisplayMode {
+        Color,
+        SampleCount,
+        Error,
+    }
+
+    impl DisplayMode {
+        pub const ALL: [DisplayMode; 3] = [DisplayMode::Color, DisplayMode::SampleCount, DisplayMode::Error];
+
+        pub fn id(&self) -> i32 {
+            match self {
+                DisplayMode::Color => 0,
+                DisplayMode::SampleCount => 1,
+                DisplayMode::Error => 2,
+            }
+        }
+
+        pub fn name(&self) -> &'static str {
+            match self {
+                DisplayMode::Color => "Color",
+                DisplayMode::SampleCount => "Sample Count",
+                DisplayMode::Error => "Relative Error",
+            }
+        }
+    }
+
+    // Path tracer quality knobs, uploaded as uniforms every frame
+    #[derive(Clone, Copy)]
+    pub struct RenderSettings {
+        pub max_bounces: i32,
+        pub samples_per_pixel: i32,
+        pub rr_min_depth: i32,
+        pub is_spectral: bool,
+        pub is_low_discrepancy: bool,
+        pub is_adaptive: bool,
+        pub adaptive_threshold: f32,
+        pub adaptive_min_samples: i32,
+        pub adaptive_max_factor: i32,
+        pub display_mode: DisplayMode,
+        pub render_scale: f32,
+        pub is_fixed_resolution: bool,
+        pub fixed_resolution: [u32; 2],
+        pub tile_size: u32,
+        pub shutter_open: f32,
+        pub shutter_close: f32,
+    }
+
+    impl RenderSettings {
+        pub fn new() -> Self {
+            Self {
+                max_bounces: 5,
+                samples_per_pixel: 5,
+                rr_min_depth: 3,
+                is_spectral: false,
+                is_low_discrepancy: true,
+                is_adaptive: true,
+                adaptive_threshold: 0.02,
+                adaptive_min_samples: 64,
+                adaptive_max_factor: 4,
+                display_mode: DisplayMode::Color,
+                render_scale: 1.0,
+                is_fixed_resolution: false,
+                fixed_resolution: [1920, 1080],
+                tile_size: 0,
+                shutter_open: 0.0,
+                shutter_close: 0.0,
+            }
+        }
+
+        // Size of the image the compute shader renders, the quad stretches it over the window
+        pub fn resolution(&self, window_width: u32, window_height: u32) -> (u32, u32) {
+            if self.is_fixed_resolution {
+                (self.fixed_resolution[0].max(1), self.fixed_resolution[1].max(1))
+            } else {
+                (
+                    ((window_width as f32 * self.render_scale) as u32).max(1),
+                    ((window_height as f32 * self.render_scale) as u32).max(1),
+                )
+            }
+        }
+
+        pub fn ui(&mut self, ui: &mut Ui) {
+            ui.add(Slider::new(&mut self.max_bounces, 1..=64).text("Max Bounces"));
+            ui.add(Slider::new(&mut self.samples_per_pixel, 1..=64).text("Samples Per Pixel"))
+                .on_hover_text("Paths traced per pixel in each dispatch");
+            ui.add(Slider::new(&mut self.rr_min_depth, 0..=64).text("Russian Roulette Depth"))
+                .on_hover_text("Bounces before paths with low throughput may be terminated early");
+            ui.add(egui::Checkbox::new(&mut self.is_spectral, "Spectral Rendering"))
+                .on_hover_text("Trace one wavelength per path so glass disperses light");
+            ui.add(egui::Checkbox::new(&mut self.is_low_discrepancy, "Low Discrepancy Sampling"))
+                .on_hover_text("Owen scrambled Sobol samples for the camera, otherwise plain PCG random numbers");
+            ui.collapsing("Adaptive Sampling", |ui| {
+                ui.add(egui::Checkbox::new(&mut self.is_adaptive, "Enabled"))
+                    .on_hover_text("Stop tracing converged pixels and spend more samples on noisy ones");
+                ui.add(Slider::new(&mut self.adaptive_threshold, 0.001..=0.2).text("Error Threshold").logarithmic(true));
+                ui.add(Slider::new(&mut self.adaptive_min_samples, 1..=1024).text("Min Samples").logarithmic(true));
+                ui.add(Slider::new(&mut self.adaptive_max_factor, 1..=16).text("Max Sample Factor"));
+            });
+            ui.collapsing("Resolution", |ui| {
+                ui.add(egui::Checkbox::new(&mut self.is_fixed_resolution, "Fixed Output Size"));
+                if self.is_fixed_resolution {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut self.fixed_resolution[0]).clamp_range(1..=8192));
+                        ui.label("x");
+                        ui.add(egui::DragValue::new(&mut self.fixed_resolution[1]).clamp_range(1..=8192));
+                    });
+                } else {
+                    ui.add(Slider::new(&mut self.render_scale, 0.1..=2.0).text("Render Scale"));
+                }
+                ui.add(Slider::new(&mut self.tile_size, 0..=1024).text("Tile Size"))
+                    .on_hover_text("Render in separate dispatches of this many pixels, 0 renders the whole image at once");
+            });
+            ui.collapsing("Motion Blur", |ui| {
+                ui.add(Slider::new(&mut self.shutter_open, -1.0..=1.0).text("Shutter Open"));
+                ui.add(Slider::new(&mut self.shutter_close, -1.0..=1.0).text("Shutter Close"))
+                    .on_hover_text("Seconds relative to the current frame, objects move by their velocity within the interval");
+                if self.shutter_close < self.shutter_open {
+                    self.shutter_close = self.shutter_open;
+                }
+            });
+            egui::ComboBox::from_label("Display")
+                .selected_text(self.display_mode.name())
+                .show_ui(ui, |ui| {
+                    for mode in DisplayMode::ALL.iter() {
+                        ui.selectable_value(&mut self.display_mode, *mode, mode.name());
+                    }
+                });
+            if ui.button("Reset").clicked() {
+                *self = RenderSettings::new();
+            }
+        }
+    }
+
+    // Settings of the array and scatter tools in the instance editor
+    #[derive(Clone)]
+    pub struct InstanceTools {
+        pub mesh: usize,
+        pub origin: [f32; 3],
+        pub array_counts: [u32; 3],
+        pub array_spacing: [f32; 3],
+        pub scatter_count: u32,
+        pub scatter_extent: [f32; 3],
+        pub scatter_scale: [f32; 2],
+        pub scatter_rotation: bool,
+        pub seed: u32,
+    }
+
+    impl InstanceTools {
+        pub fn new() -> Self {
+            Self {
+                mesh: 0,
+                origin: [0.0; 3],
+                array_counts: [4, 1, 4],
+                array_spacing: [2.0; 3],
+                scatter_count: 100,
+                scatter_extent: [20.0, 0.0, 20.0],
+                scatter_scale: [0.5, 1.5],
+                scatter_rotation: true,
+                seed: 1,
+            }
+        }
+    }
 
     #[derive(Clone)]
     pub struct SandboxWindow {
@@ -9,6 +181,30 @@ pub mod windows{
         pub new_Object: Object,
         pub skycolor: [f32; 3],
         pub is_fisheye:bool,
+        pub render_settings: RenderSettings,
+        pub physics_settings: PhysicsSettings,
+        pub simulation: SimulationControls,
+        pub joints: Vec<Joint>,
+        pub joint_tools: JointTools,
+        pub force_fields: Vec<ForceField>,
+        pub emitters: Vec<ParticleEmitter>,
+        pub particles: ParticleSystem,
+        pub cloths: Vec<Cloth>,
+        // Object picked by clicking in the viewport
+        pub selected: Option<usize>,
+        // Opens the selected object in the object list on the next frame
+        pub reveal_selected: bool,
+        pub fog_density: f32,
+        pub fog_albedo: [f32; 3],
+        pub fog_anisotropy: f32,
+        pub fog_extent: f32,
+        pub texture_graphs: Vec<TextureGraph>,
+        pub texture_previews: Vec<Option<(TextureGraph, egui::TextureHandle)>>,
+        pub meshes: Vec<Mesh>,
+        pub instances: Vec<Instance>,
+        pub instance_tools: InstanceTools,
+        pub mesh_path: String,
+        pub scene_path: String,
     }
     
     impl SandboxWindow {
@@ -22,25 +218,64 @@ pub mod windows{
                     roughness: 1.0,
                     emission: 0.0,
                     is_static: true,
+                    is_sleeping: false,
                     velocity: [0.0; 3],
                     acceleration: [0.0; 3],
                     angular_velocity: [0.0; 3],
                     angular_acceleration: [0.0; 3],
                     orientation: [1.0, 0.0, 0.0, 0.0],
                     mass: 1.0,
+                    restitution: 0.3,
+                    friction: 0.5,
+                    angular_damping: 0.1,
+                    max_speed: 0.0,
                     is_cube: true,
                     size: [0.0;3],
                     is_glass: false,
                     reflectness: 0.0,
+                    textures: [-1; 3],
+                    is_volume: false,
+                    medium: [0.5, 0.05, 0.0],
+                    subsurface: 0.0,
+                    subsurface_radius: [1.0, 0.2, 0.1],
+                    ior: 1.5,
+                    dispersion: 0.0042,
                 },
                 is_fisheye:false,
+                render_settings: RenderSettings::new(),
+                physics_settings: PhysicsSettings::new(),
+                simulation: SimulationControls::new(),
+                joints: Vec::new(),
+                joint_tools: JointTools::new(),
+                force_fields: Vec::new(),
+                emitters: Vec::new(),
+                particles: ParticleSystem::new(),
+                cloths: Vec::new(),
+                selected: None,
+                reveal_selected: false,
                 skycolor: [30.0,255.0,255.0],
+                fog_density: 0.0,
+                fog_albedo: [0.9; 3],
+                fog_anisotropy: 0.3,
+                fog_extent: 50.0,
+                texture_graphs: Vec::new(),
+                texture_previews: Vec::new(),
+                meshes: BUILTIN_MESHES.iter().filter_map(|name| Mesh::load(name).ok()).collect(),
+                instances: Vec::new(),
+                instance_tools: InstanceTools::new(),
+                mesh_path: "triangle.obj".to_string(),
+                scene_path: "scene.txt".to_string(),
             }
         }
     
     pub fn ui(&mut self, ctx: &egui::Context, ui: &mut Ui) {
-        let _ = ctx;
             self.add_new_object(ui);
+            self.texture_editor(ctx, ui);
+            self.instance_editor(ui);
+            self.joint_editor(ui);
+            self.field_editor(ui);
+            self.emitter_editor(ui);
+            self.cloth_editor(ui);
             self.scene_settings(ui);
         
     }
@@ -93,12 +328,18 @@ pub mod windows{
                         Slider::new(&mut self.new_Object.emission, 0.0..=100.0)
                             .text("Emission"),
                     );
+                    subsurface_ui(ui, &mut self.new_Object.subsurface, &mut self.new_Object.subsurface_radius);
                     ui.add(egui::Checkbox::new(&mut self.new_Object.is_glass, "Make it Glass"));
                     if(self.new_Object.is_glass){
                         ui.add(
                             Slider::new(&mut self.new_Object.reflectness, 0.0..=10.0)
                                 .text("Reflectness"),
                         );
+                        glass_ui(ui, &mut self.new_Object.ior, &mut self.new_Object.dispersion);
+                    }
+                    ui.add(egui::Checkbox::new(&mut self.new_Object.is_volume, "Make it Volume"));
+                    if self.new_Object.is_volume {
+                        medium_ui(ui, &mut self.new_Object.medium);
                     }
                     ui.add(egui::Checkbox::new(&mut self.new_Object.is_static, "Make it Static"));
                     ui.add(egui::Checkbox::new(&mut self.new_Object.is_cube, "Make it Cube"));
@@ -110,16 +351,28 @@ pub mod windows{
                             roughness: self.new_Object.roughness,
                             emission: self.new_Object.emission,
                             is_static:self.new_Object.is_static,
+                            is_sleeping: false,
                             velocity: [0.0; 3],
                             acceleration: [0.0; 3],
                             angular_velocity: [0.0;3],
                             angular_acceleration: [0.0;3],
                             orientation: [1.0, 0.0, 0.0, 0.0],
                             mass: 1.0,
+                            restitution: 0.3,
+                            friction: 0.5,
+                            angular_damping: 0.1,
+                            max_speed: 0.0,
                             is_cube: self.new_Object.is_cube,
                             size:[1.0;3],
                             is_glass: self.new_Object.is_glass,
                             reflectness: self.new_Object.reflectness,
+                            textures: [-1; 3],
+                            is_volume: self.new_Object.is_volume,
+                            medium: self.new_Object.medium,
+                            subsurface: self.new_Object.subsurface,
+                            subsurface_radius: self.new_Object.subsurface_radius,
+                            ior: self.new_Object.ior,
+                            dispersion: self.new_Object.dispersion,
                         });
                         self.new_Object= Object {
                             position: [0.0; 3],
@@ -128,16 +381,28 @@ pub mod windows{
                             roughness: 1.0,
                             emission: 0.0,
                             is_static: true,
+                            is_sleeping: false,
                             velocity: [0.0; 3],
                             acceleration: [0.0; 3],
                             angular_velocity: [0.0; 3],
                             angular_acceleration: [0.0; 3],
                             orientation: [1.0, 0.0, 0.0, 0.0],
                             mass: 1.0,
+                            restitution: 0.3,
+                            friction: 0.5,
+                            angular_damping: 0.1,
+                            max_speed: 0.0,
                             is_cube: true,
                             size:[1.0;3],
                             is_glass: false,
                             reflectness: 0.0,
+                            textures: [-1; 3],
+                            is_volume: false,
+                            medium: [0.5, 0.05, 0.0],
+                            subsurface: 0.0,
+                            subsurface_radius: [1.0, 0.2, 0.1],
+                            ior: 1.5,
+                            dispersion: 0.0042,
                         };
                     }
                 });
@@ -152,11 +417,470 @@ pub mod windows{
                 ui.add(Slider::new(&mut self.skycolor[2], 0.0..=255.0).text("B"));
             });
             ui.add(egui::Checkbox::new(&mut self.is_fisheye, "Fisheye Effect"));
+            ui.collapsing("Fog", |ui| {
+                ui.add(Slider::new(&mut self.fog_density, 0.0..=1.0).text("Density").logarithmic(true));
+                ui.horizontal(|ui| {
+                    ui.label("Albedo");
+                    ui.color_edit_button_rgb(&mut self.fog_albedo);
+                });
+                ui.add(Slider::new(&mut self.fog_anisotropy, -0.95..=0.95).text("Anisotropy"));
+                ui.add(Slider::new(&mut self.fog_extent, 1.0..=500.0).text("Sky Distance"));
+            });
+            ui.collapsing("Physics", |ui| {
+                self.physics_settings.ui(ui);
+            });
+        }
+
+        // Selects the first object along a ray from the camera, or clears the selection
+        pub fn select_at(&mut self, world: &mut PhysicsWorld, origin: [f32; 3], direction: [f32; 3]) -> Option<Hit> {
+            world.sync_from_scene(&self.Objects);
+            let hit = world.raycast(origin, direction, f32::MAX);
+            self.selected = hit.map(|hit| hit.object);
+            self.reveal_selected = self.selected.is_some();
+            hit
+        }
+
+        pub fn cloth_editor(&mut self, ui: &mut Ui) {
+            ui.vertical_centered(|ui| {
+                ui.collapsing(format!("Cloth & Soft Bodies ({})", self.cloths.len()), |ui| {
+                    let mut remove_cloth = None;
+                    for (c, cloth) in self.cloths.iter_mut().enumerate() {
+                        ui.push_id(("cloth", c), |ui| {
+                            ui.collapsing(format!("Cloth {}: {} ({} triangles)", c, cloth.source, cloth.mesh.triangles.len()), |ui| {
+                                cloth.ui(ui);
+                                if ui.button("Remove Cloth").clicked() {
+                                    remove_cloth = Some(c);
+                                }
+                            });
+                        });
+                    }
+                    if let Some(c) = remove_cloth {
+                        self.cloths.remove(c);
+                    }
+                    ui.horizontal(|ui| {
+                        for (label, source) in [("Add Cloth", GRID_SOURCE), ("Add Soft Body", "sphere")] {
+                            if ui.button(label).clicked() {
+                                match Cloth::new(source, [0.0, 3.0, 0.0]) {
+                                    Ok(cloth) => self.cloths.push(cloth),
+                                    Err(e) => eprintln!("Error building cloth: {}", e),
+                                }
+                            }
+                        }
+                    });
+                });
+            });
+        }
+
+        pub fn emitter_editor(&mut self, ui: &mut Ui) {
+            ui.vertical_centered(|ui| {
+                ui.collapsing(format!("Particle Emitters ({} particles)", self.particles.count()), |ui| {
+                    let mut remove_emitter = None;
+                    for (e, emitter) in self.emitters.iter_mut().enumerate() {
+                        ui.push_id(("emitter", e), |ui| {
+                            ui.collapsing(format!("Emitter {}", e), |ui| {
+                                emitter.ui(ui);
+                                if ui.button("Remove Emitter").clicked() {
+                                    remove_emitter = Some(e);
+                                }
+                            });
+                        });
+                    }
+                    if let Some(e) = remove_emitter {
+                        self.emitters.remove(e);
+                        self.particles.remove(e);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Add Sparks").clicked() {
+                            self.emitters.push(ParticleEmitter::sparks());
+                        }
+                        if ui.button("Add Dust").clicked() {
+                            self.emitters.push(ParticleEmitter::dust());
+                        }
+                        if ui.button("Add Debris").clicked() {
+                            self.emitters.push(ParticleEmitter::debris());
+                        }
+                        if ui.button("Clear Particles").clicked() {
+                            self.particles.clear();
+                        }
+                    });
+                });
+            });
+        }
+
+        pub fn field_editor(&mut self, ui: &mut Ui) {
+            ui.vertical_centered(|ui| {
+                ui.collapsing(format!("Force Fields ({})", self.force_fields.len()), |ui| {
+                    let mut remove_field = None;
+                    for (f, field) in self.force_fields.iter_mut().enumerate() {
+                        ui.push_id(("field", f), |ui| {
+                            ui.collapsing(format!("Field {}: {}", f, field.kind.name()), |ui| {
+                                field.ui(ui);
+                                if ui.button("Remove Field").clicked() {
+                                    remove_field = Some(f);
+                                }
+                            });
+                        });
+                    }
+                    if let Some(f) = remove_field {
+                        self.force_fields.remove(f);
+                    }
+                    if ui.button("Add Field").clicked() {
+                        self.force_fields.push(ForceField::new(FieldKind::Wind));
+                    }
+                });
+            });
+        }
+
+        pub fn joint_editor(&mut self, ui: &mut Ui) {
+            ui.vertical_centered(|ui| {
+                ui.collapsing(format!("Joints ({})", self.joints.len()), |ui| {
+                    self.joint_tools.ui(ui, &self.Objects, &mut self.joints);
+                });
+            });
+        }
+
+        pub fn instance_editor(&mut self, ui: &mut Ui) {
+            ui.vertical_centered(|ui| {
+                ui.collapsing("Meshes & Instances", |ui| {
+                    // Shared meshes and their default material
+                    let mut remove_mesh = None;
+                    for (m, mesh) in self.meshes.iter_mut().enumerate() {
+                        ui.push_id(("mesh", m), |ui| {
+                            ui.collapsing(format!("Mesh {}: {} ({} triangles)", m, mesh.source, mesh.triangles.len()), |ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label("Color");
+                                    let mut color = [mesh.color[0] / 255.0, mesh.color[1] / 255.0, mesh.color[2] / 255.0];
+                                    if ui.color_edit_button_rgb(&mut color).changed() {
+                                        mesh.color = [color[0] * 255.0, color[1] * 255.0, color[2] * 255.0];
+                                    }
+                                });
+                                ui.add(Slider::new(&mut mesh.roughness, 0.0..=1.0).text("Roughness"));
+                                ui.add(Slider::new(&mut mesh.emission, 0.0..=100.0).text("Emission"));
+                                if ui.button("Remove Mesh").clicked() {
+                                    remove_mesh = Some(m);
+                                }
+                            });
+                        });
+                    }
+                    if let Some(m) = remove_mesh {
+                        self.meshes.remove(m);
+                        self.instances.retain(|instance| instance.mesh != m);
+                        for instance in self.instances.iter_mut() {
+                            if instance.mesh > m {
+                                instance.mesh -= 1;
+                            }
+                        }
+                    }
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut self.mesh_path);
+                        if ui.button("Load Mesh").clicked() {
+                            match Mesh::load(&self.mesh_path) {
+                                Ok(mesh) => self.meshes.push(mesh),
+                                Err(e) => eprintln!("Error loading mesh {}: {}", self.mesh_path, e),
+                            }
+                        }
+                    });
+                    if self.meshes.is_empty() {
+                        return;
+                    }
+
+                    ui.separator();
+                    let meshes = &self.meshes;
+                    let instances = &mut self.instances;
+                    let mesh_name = |m: usize| meshes.get(m).map_or("None".to_string(), |mesh| format!("{}: {}", m, mesh.source));
+                    let mut remove_instance = None;
+                    ui.collapsing(format!("Instances ({})", instances.len()), |ui| {
+                        egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                            for (i, instance) in instances.iter_mut().enumerate() {
+                                ui.push_id(("instance", i), |ui| {
+                                    ui.collapsing(format!("Instance {} ({})", i, mesh_name(instance.mesh)), |ui| {
+                                        instance_ui(ui, instance, meshes.len(), &mesh_name);
+                                        if ui.button("Remove Instance").clicked() {
+                                            remove_instance = Some(i);
+                                        }
+                                    });
+                                });
+                            }
+                        });
+                    });
+                    if let Some(i) = remove_instance {
+                        instances.remove(i);
+                    }
+
+                    // Array and scatter tools
+                    let tools = &mut self.instance_tools;
+                    tools.mesh = tools.mesh.min(meshes.len() - 1);
+                    ui.collapsing("Instance Tools", |ui| {
+                        egui::ComboBox::from_label("Mesh")
+                            .selected_text(mesh_name(tools.mesh))
+                            .show_ui(ui, |ui| {
+                                for m in 0..meshes.len() {
+                                    ui.selectable_value(&mut tools.mesh, m, mesh_name(m));
+                                }
+                            });
+                        ui.horizontal(|ui| {
+                            ui.label("Origin");
+                            for axis in 0..3 {
+                                ui.add(egui::DragValue::new(&mut tools.origin[axis]).speed(0.1));
+                            }
+                        });
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label("Array Count");
+                            for axis in 0..3 {
+                                ui.add(egui::DragValue::new(&mut tools.array_counts[axis]).clamp_range(1..=100));
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Spacing");
+                            for axis in 0..3 {
+                                ui.add(egui::DragValue::new(&mut tools.array_spacing[axis]).speed(0.1));
+                            }
+                        });
+                        if ui.button("Create Array").clicked() {
+                            instances.extend(instance_grid(tools.mesh, tools.origin, tools.array_counts, tools.array_spacing));
+                        }
+                        ui.separator();
+                        ui.add(Slider::new(&mut tools.scatter_count, 1..=10000).text("Scatter Count").logarithmic(true));
+                        ui.horizontal(|ui| {
+                            ui.label("Extent");
+                            for axis in 0..3 {
+                                ui.add(egui::DragValue::new(&mut tools.scatter_extent[axis]).speed(0.1).clamp_range(0.0..=1000.0));
+                            }
+                        });
+                        ui.add(Slider::new(&mut tools.scatter_scale[0], 0.01..=10.0).text("Min Scale"));
+                        ui.add(Slider::new(&mut tools.scatter_scale[1], 0.01..=10.0).text("Max Scale"));
+                        ui.add(egui::Checkbox::new(&mut tools.scatter_rotation, "Random Rotation"));
+                        ui.add(egui::DragValue::new(&mut tools.seed).prefix("Seed "));
+                        if ui.button("Scatter").clicked() {
+                            instances.extend(instance_scatter(
+                                tools.mesh,
+                                tools.origin,
+                                tools.scatter_extent,
+                                tools.scatter_count,
+                                tools.scatter_scale,
+                                tools.scatter_rotation,
+                                tools.seed,
+                            ));
+                            // Next scatter gets a different distribution
+                            tools.seed = tools.seed.wrapping_add(1);
+                        }
+                        ui.separator();
+                        if ui.button("Clear Instances").clicked() {
+                            instances.clear();
+                        }
+                    });
+                });
+            });
+        }
+
+        pub fn texture_editor(&mut self, ctx: &egui::Context, ui: &mut Ui) {
+            self.texture_previews.resize(self.texture_graphs.len(), None);
+            ui.vertical_centered(|ui| {
+                ui.collapsing("Procedural Textures", |ui| {
+                    let mut remove_graph = None;
+                    let texture_previews = &mut self.texture_previews;
+                    for (g, graph) in self.texture_graphs.iter_mut().enumerate() {
+                        ui.push_id(("texture_graph", g), |ui| {
+                            ui.collapsing(format!("Texture {}: {}", g, graph.name), |ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label("Name");
+                                    ui.text_edit_singleline(&mut graph.name);
+                                });
+                                let mut remove_node = None;
+                                for (n, node) in graph.nodes.iter_mut().enumerate() {
+                                    ui.push_id(n, |ui| {
+                                        ui.separator();
+                                        texture_node_ui(ui, n, node);
+                                        if ui.button("Remove Node").clicked() {
+                                            remove_node = Some(n);
+                                        }
+                                    });
+                                }
+                                if let Some(n) = remove_node {
+                                    graph.nodes.remove(n);
+                                    // Keep the remaining links pointing at the same nodes
+                                    for node in graph.nodes.iter_mut() {
+                                        for input in [&mut node.input_a, &mut node.input_b] {
+                                            if *input == n as i32 {
+                                                *input = -1;
+                                            } else if *input > n as i32 {
+                                                *input -= 1;
+                                            }
+                                        }
+                                    }
+                                }
+                                ui.separator();
+                                ui.horizontal(|ui| {
+                                    if ui.button("Add Node").clicked() {
+                                        let mut node = TextureNode::new(PatternKind::Noise);
+                                        // Feed the previous output into the new node by default
+                                        node.input_a = graph.nodes.len() as i32 - 1;
+                                        graph.nodes.push(node);
+                                    }
+                                    if ui.button("Remove Texture").clicked() {
+                                        remove_graph = Some(g);
+                                    }
+                                });
+                                ui.label("Output (last node):");
+                                let preview = texture_preview(ctx, &mut texture_previews[g], graph);
+                                ui.image((preview.id(), egui::vec2(96.0, 96.0)));
+                            });
+                        });
+                    }
+                    if let Some(g) = remove_graph {
+                        self.texture_graphs.remove(g);
+                        self.texture_previews.remove(g);
+                        for object in self.Objects.iter_mut().chain(self.simulation.stored_objects_mut()) {
+                            for slot in object.textures.iter_mut() {
+                                if *slot == g as i32 {
+                                    *slot = -1;
+                                } else if *slot > g as i32 {
+                                    *slot -= 1;
+                                }
+                            }
+                        }
+                    }
+                    if self.texture_graphs.len() < MAX_TEXTURE_GRAPHS && ui.button("Add Texture").clicked() {
+                        self.texture_graphs.push(TextureGraph::new(&format!("Texture {}", self.texture_graphs.len())));
+                        self.texture_previews.push(None);
+                    }
+                });
+            });
         }
         
     }
+
+    // Scattering, absorption and anisotropy of a volume interior
+    fn medium_ui(ui: &mut Ui, medium: &mut [f32; 3]) {
+        ui.add(Slider::new(&mut medium[0], 0.0..=10.0).text("Scattering"));
+        ui.add(Slider::new(&mut medium[1], 0.0..=10.0).text("Absorption"));
+        ui.add(Slider::new(&mut medium[2], -0.95..=0.95).text("Anisotropy"));
+    }
+
+    // Refractive index and Cauchy dispersion of glass
+    fn glass_ui(ui: &mut Ui, ior: &mut f32, dispersion: &mut f32) {
+        ui.add(Slider::new(ior, 1.0..=3.0).text("Refractive Index"));
+        ui.add(Slider::new(dispersion, 0.0..=0.05).text("Dispersion"))
+            .on_hover_text("Only visible with spectral rendering");
+    }
+
+    // Subsurface weight and scattering radius per color channel
+    fn subsurface_ui(ui: &mut Ui, subsurface: &mut f32, radius: &mut [f32; 3]) {
+        ui.add(Slider::new(subsurface, 0.0..=1.0).text("Subsurface"));
+        if *subsurface > 0.0 {
+            ui.add(Slider::new(&mut radius[0], 0.001..=5.0).text("Radius R").logarithmic(true));
+            ui.add(Slider::new(&mut radius[1], 0.001..=5.0).text("Radius G").logarithmic(true));
+            ui.add(Slider::new(&mut radius[2], 0.001..=5.0).text("Radius B").logarithmic(true));
+        }
+    }
+
+    fn texture_node_ui(ui: &mut Ui, index: usize, node: &mut TextureNode) {
+        ui.horizontal(|ui| {
+            ui.label(format!("Node {}", index));
+            egui::ComboBox::from_id_source("pattern")
+                .selected_text(node.kind.name())
+                .show_ui(ui, |ui| {
+                    for kind in PatternKind::ALL {
+                        if ui.selectable_value(&mut node.kind, kind, kind.name()).clicked() {
+                            node.param = kind.default_param();
+                        }
+                    }
+                });
+        });
+        ui.add(Slider::new(&mut node.scale, 0.01..=20.0).text("Scale"));
+        ui.add(Slider::new(&mut node.param, node.kind.param_range()).text(node.kind.param_label()));
+        ui.horizontal(|ui| {
+            texture_input_ui(ui, "A", index, &mut node.input_a, &mut node.color_a);
+            texture_input_ui(ui, "B", index, &mut node.input_b, &mut node.color_b);
+        });
+    }
+
+    // Either a constant color or the output of an earlier node
+    fn texture_input_ui(ui: &mut Ui, label: &str, index: usize, input: &mut i32, color: &mut [f32; 3]) {
+        ui.label(label);
+        let selected = if *input < 0 { "Color".to_string() } else { format!("Node {}", input) };
+        egui::ComboBox::from_id_source(label)
+            .width(70.0)
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(input, -1, "Color");
+                for i in 0..index {
+                    ui.selectable_value(input, i as i32, format!("Node {}", i));
+                }
+            });
+        if *input < 0 {
+            ui.color_edit_button_rgb(color);
+        }
+    }
+
+    // Samples the graph on the z = 0 plane, only re-rendered when the graph changes
+    fn instance_ui(ui: &mut Ui, instance: &mut Instance, mesh_count: usize, mesh_name: &dyn Fn(usize) -> String) {
+        egui::ComboBox::from_label("Mesh")
+            .selected_text(mesh_name(instance.mesh))
+            .show_ui(ui, |ui| {
+                for m in 0..mesh_count {
+                    ui.selectable_value(&mut instance.mesh, m, mesh_name(m));
+                }
+            });
+        ui.add(Slider::new(&mut instance.position[0], -100.0..=100.0).text("Position X"));
+        ui.add(Slider::new(&mut instance.position[1], -100.0..=100.0).text("Position Y"));
+        ui.add(Slider::new(&mut instance.position[2], -100.0..=100.0).text("Position Z"));
+        ui.add(Slider::new(&mut instance.rotation[0], -180.0..=180.0).text("Rotation X"));
+        ui.add(Slider::new(&mut instance.rotation[1], -180.0..=180.0).text("Rotation Y"));
+        ui.add(Slider::new(&mut instance.rotation[2], -180.0..=180.0).text("Rotation Z"));
+        ui.add(Slider::new(&mut instance.scale, 0.01..=100.0).text("Scale").logarithmic(true));
+        ui.add(egui::Checkbox::new(&mut instance.override_material, "Override Material"));
+        if instance.override_material {
+            ui.add(Slider::new(&mut instance.color[0], 0.0..=255.0).text("Color R"));
+            ui.add(Slider::new(&mut instance.color[1], 0.0..=255.0).text("Color G"));
+            ui.add(Slider::new(&mut instance.color[2], 0.0..=255.0).text("Color B"));
+            ui.add(Slider::new(&mut instance.roughness, 0.0..=1.0).text("Roughness"));
+            ui.add(Slider::new(&mut instance.emission, 0.0..=100.0).text("Emission"));
+        }
+    }
+
+    fn texture_preview(ctx: &egui::Context, cache: &mut Option<(TextureGraph, egui::TextureHandle)>, graph: &TextureGraph) -> egui::TextureHandle {
+        if let Some((cached_graph, handle)) = cache {
+            if cached_graph == graph {
+                return handle.clone();
+            }
+        }
+        let size = 64;
+        let samples = 4;
+        let mut pixels = Vec::with_capacity(size * size);
+        for y in 0..size {
+            for x in 0..size {
+                // Jitter within the pixel with the same sequence the path tracer uses
+                let sampler = Sampler::new(x as u32, y as u32);
+                let mut color = [0.0; 3];
+                for i in 0..samples {
+                    let jitter = sampler.sample(i);
+                    let p = [
+                        ((x as f32 + jitter[0]) / size as f32) * 4.0 - 2.0,
+                        2.0 - ((y as f32 + jitter[1]) / size as f32) * 4.0,
+                        0.0,
+                    ];
+                    let sample = graph.evaluate(p);
+                    for c in 0..3 {
+                        color[c] += sample[c] / samples as f32;
+                    }
+                }
+                pixels.push(egui::Color32::from_rgb(
+                    (color[0].clamp(0.0, 1.0) * 255.0) as u8,
+                    (color[1].clamp(0.0, 1.0) * 255.0) as u8,
+                    (color[2].clamp(0.0, 1.0) * 255.0) as u8,
+                ));
+            }
+        }
+        let image = egui::ColorImage { size: [size, size], pixels };
+        let handle = ctx.load_texture("texture_preview", image, egui::TextureOptions::NEAREST);
+        *cache = Some((graph.clone(), handle.clone()));
+        handle
+    }
     pub struct MainWindow<'a> {
         pub show_sandbox_window: bool,
+        pub show_render_settings: bool,
         pub sandbox_window: &'a mut SandboxWindow,
     }
     
@@ -164,6 +888,7 @@ pub mod windows{
         pub fn new(sandbox_window: &'a mut SandboxWindow) -> Self {
             Self {
                 show_sandbox_window: false,
+                show_render_settings: false,
                 sandbox_window,
             }
         }
@@ -190,6 +915,14 @@ pub mod windows{
                                 self.sandbox_window.ui(ctx, ui);
                             });
                     }
+                    if self.show_render_settings {
+                        egui::Window::new("Render Settings")
+                            .resizable(true)
+                            .default_width(300.0)
+                            .show(ctx, |ui| {
+                                self.sandbox_window.render_settings.ui(ui);
+                            });
+                    }
                     ui.hyperlink_to(
                         format!("{GITHUB} Resource Code"),
                         "https://github.com/OmarDevX",
@@ -204,9 +937,16 @@ pub mod windows{
     
             egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
                 egui::menu::bar(ui, |ui| {
-                    file_menu_button(ui);
+                    file_menu_button(ui, self.sandbox_window);
+                    ui.separator();
+                    self.sandbox_window.simulation.ui(ui);
                 });
             });
+            if self.sandbox_window.simulation.recorder.has_frames() {
+                egui::TopBottomPanel::bottom("timeline").show(ctx, |ui| {
+                    self.sandbox_window.simulation.timeline_ui(ui);
+                });
+            }
         }
     
         pub fn demo_list_ui(&mut self, ui: &mut egui::Ui) {
@@ -215,48 +955,107 @@ pub mod windows{
                     ui.label("Placeholder");
                 ui.vertical_centered(|ui| {
                     ui.label("Objects:");
-                    ui.collapsing("Object List", |ui| {
+                    let reveal = self.sandbox_window.reveal_selected;
+                    let selected = self.sandbox_window.selected;
+                    self.sandbox_window.reveal_selected = false;
+                    egui::CollapsingHeader::new("Object List").open(reveal.then_some(true)).show(ui, |ui| {
+                        let texture_graphs = &self.sandbox_window.texture_graphs;
                         for (i, object) in self.sandbox_window.Objects.iter_mut().enumerate() {
                             ui.push_id(i, |ui| {
-                                ui.collapsing(format!("Object {}", i), |ui| {
-                                    // Object Position Sliders
-                                    ui.vertical(|ui| {
-                                        ui.label(format!("Object Position"));
-                                        ui.add(Slider::new(&mut object.position[0], -100.0..=100.0).text("Position X"));
-                                        ui.add(Slider::new(&mut object.position[1], -100.0..=100.0).text("Position Y"));
-                                        ui.add(Slider::new(&mut object.position[2], -100.0..=100.0).text("Position Z"));
-                                    });
+                                let is_selected = selected == Some(i);
+                                let title = if is_selected { format!("Object {} (selected)", i) } else { format!("Object {}", i) };
+                                let header = egui::CollapsingHeader::new(title)
+                                    .id_source("object")
+                                    .open((reveal && is_selected).then_some(true))
+                                    .show(ui, |ui| {
+                                        // Object Position Sliders
+                                        ui.vertical(|ui| {
+                                            ui.label(format!("Object Position"));
+                                            ui.add(Slider::new(&mut object.position[0], -100.0..=100.0).text("Position X"));
+                                            ui.add(Slider::new(&mut object.position[1], -100.0..=100.0).text("Position Y"));
+                                            ui.add(Slider::new(&mut object.position[2], -100.0..=100.0).text("Position Z"));
+                                        });
+
+                                        // Velocities, also used for shutter motion blur, and contact response
+                                        ui.collapsing("Motion", |ui| {
+                                            ui.add(Slider::new(&mut object.mass, 0.1..=100.0).logarithmic(true).text("Mass"));
+                                            ui.add(Slider::new(&mut object.restitution, 0.0..=1.0).text("Restitution"));
+                                            ui.add(Slider::new(&mut object.friction, 0.0..=2.0).text("Friction"));
+                                            ui.add(Slider::new(&mut object.angular_damping, 0.0..=10.0).logarithmic(true).text("Angular Damping"));
+                                            ui.add(Slider::new(&mut object.max_speed, 0.0..=500.0).text("Max Speed"))
+                                                .on_hover_text("Caps the linear speed, 0 for no limit. Fast bodies are swept so they don't tunnel either way");
+                                            ui.add(egui::Checkbox::new(&mut object.is_sleeping, "Sleeping"));
+                                            ui.add(Slider::new(&mut object.velocity[0], -50.0..=50.0).text("Velocity X"));
+                                            ui.add(Slider::new(&mut object.velocity[1], -50.0..=50.0).text("Velocity Y"));
+                                            ui.add(Slider::new(&mut object.velocity[2], -50.0..=50.0).text("Velocity Z"));
+                                            ui.add(Slider::new(&mut object.angular_velocity[0], -20.0..=20.0).text("Angular X"));
+                                            ui.add(Slider::new(&mut object.angular_velocity[1], -20.0..=20.0).text("Angular Y"));
+                                            ui.add(Slider::new(&mut object.angular_velocity[2], -20.0..=20.0).text("Angular Z"));
+                                        });
 
-                                    // Cube Size Sliders if the object is a cube
-                                    if object.is_cube {
+                                        // Cube Size Sliders if the object is a cube
+                                        if object.is_cube {
+                                            ui.vertical(|ui| {
+                                                ui.label("Cube Size");
+                                                ui.add(Slider::new(&mut object.size[0], -100.0..=100.0).text("Size X"));
+                                                ui.add(Slider::new(&mut object.size[1], -100.0..=100.0).text("Size Y"));
+                                                ui.add(Slider::new(&mut object.size[2], -100.0..=100.0).text("Size Z"));
+                                            });
+                                        }
+
+                                        // Radius and Color Sliders
                                         ui.vertical(|ui| {
-                                            ui.label("Cube Size");
-                                            ui.add(Slider::new(&mut object.size[0], -100.0..=100.0).text("Size X"));
-                                            ui.add(Slider::new(&mut object.size[1], -100.0..=100.0).text("Size Y"));
-                                            ui.add(Slider::new(&mut object.size[2], -100.0..=100.0).text("Size Z"));
+                                            ui.label("Object Color");
+                                            ui.add(Slider::new(&mut object.radius, 0.1..=100.0).text("Radius"));
+                                            ui.add(Slider::new(&mut object.color[0], 0.0..=255.0).text("Color R").clamp_to_range(true));
+                                            ui.add(Slider::new(&mut object.color[1], 0.0..=255.0).text("Color G").clamp_to_range(true));
+                                            ui.add(Slider::new(&mut object.color[2], 0.0..=255.0).text("Color B").clamp_to_range(true));
                                         });
-                                    }
 
-                                    // Radius and Color Sliders
-                                    ui.vertical(|ui| {
-                                        ui.label("Object Color");
-                                        ui.add(Slider::new(&mut object.radius, 0.1..=100.0).text("Radius"));
-                                        ui.add(Slider::new(&mut object.color[0], 0.0..=255.0).text("Color R").clamp_to_range(true));
-                                        ui.add(Slider::new(&mut object.color[1], 0.0..=255.0).text("Color G").clamp_to_range(true));
-                                        ui.add(Slider::new(&mut object.color[2], 0.0..=255.0).text("Color B").clamp_to_range(true));
-                                    });
+                                        // Roughness and Emission Sliders
+                                        ui.vertical(|ui| {
+                                            ui.label("Object Material");
+                                            ui.add(Slider::new(&mut object.roughness, 0.0..=1.0).text("Roughness").clamp_to_range(true));
+                                            ui.add(Slider::new(&mut object.emission, 0.0..=100.0).text("Emission"));
+                                            ui.add(Slider::new(&mut object.reflectness, 0.0..=100.0).text("reflect"));
+                                            subsurface_ui(ui, &mut object.subsurface, &mut object.subsurface_radius);
+                                            ui.add(egui::Checkbox::new(&mut object.is_glass, "Glass"));
+                                            if object.is_glass {
+                                                glass_ui(ui, &mut object.ior, &mut object.dispersion);
+                                            }
+                                        });
 
-                                    // Roughness and Emission Sliders
-                                    ui.vertical(|ui| {
-                                        ui.label("Object Material");
-                                        ui.add(Slider::new(&mut object.roughness, 0.0..=1.0).text("Roughness").clamp_to_range(true));
-                                        ui.add(Slider::new(&mut object.emission, 0.0..=100.0).text("Emission"));
-                                        ui.add(Slider::new(&mut object.reflectness, 0.0..=100.0).text("reflect"));
-                                    });
+                                        // Procedural texture per material parameter
+                                        ui.vertical(|ui| {
+                                            ui.label("Object Textures");
+                                            for (slot, name) in TEXTURE_SLOT_NAMES.iter().enumerate() {
+                                                let selected = texture_graphs
+                                                    .get(object.textures[slot] as usize)
+                                                    .filter(|_| object.textures[slot] >= 0)
+                                                    .map_or("None".to_string(), |graph| graph.name.clone());
+                                                egui::ComboBox::from_label(*name)
+                                                    .selected_text(selected)
+                                                    .show_ui(ui, |ui| {
+                                                        ui.selectable_value(&mut object.textures[slot], -1, "None");
+                                                        for (g, graph) in texture_graphs.iter().enumerate() {
+                                                            ui.selectable_value(&mut object.textures[slot], g as i32, graph.name.as_str());
+                                                        }
+                                                    });
+                                            }
+                                        });
 
-                                    // Static Checkbox
-                                    ui.add(egui::Checkbox::new(&mut object.is_static, "Make it Static"));
-                                });
+                                        // Volume interior, scattering is tinted by the object color
+                                        ui.add(egui::Checkbox::new(&mut object.is_volume, "Volume Interior"));
+                                        if object.is_volume {
+                                            medium_ui(ui, &mut object.medium);
+                                        }
+
+                                        // Static Checkbox
+                                        ui.add(egui::Checkbox::new(&mut object.is_static, "Make it Static"));
+                                    });
+                                if reveal && is_selected {
+                                    header.header_response.scroll_to_me(Some(egui::Align::Center));
+                                }
                             });
                             ui.separator();
                         }
@@ -264,6 +1063,9 @@ pub mod windows{
                     if ui.button("Add Sphere").clicked() {
                         self.show_sandbox_window = !self.show_sandbox_window;
                     }
+                    if ui.button("Render Settings").clicked() {
+                        self.show_render_settings = !self.show_render_settings;
+                    }
                 });
                     if ui.button("Organize windows").clicked() {
                         ui.ctx().memory_mut(|mem| mem.reset_areas());
@@ -276,7 +1078,7 @@ pub mod windows{
         }
     }
     
-        pub fn file_menu_button(ui: &mut Ui) {
+        pub fn file_menu_button(ui: &mut Ui, sandbox_window: &mut SandboxWindow) {
         let organize_shortcut =
             egui::KeyboardShortcut::new(Modifiers::CTRL | Modifiers::SHIFT, egui::Key::O);
         let reset_shortcut =
@@ -331,6 +1133,25 @@ pub mod windows{
                 ui.ctx().memory_mut(|mem| *mem = Default::default());
                 ui.close_menu();
             }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Scene File");
+                ui.text_edit_singleline(&mut sandbox_window.scene_path);
+            });
+            if ui.button("Save Scene").clicked() {
+                if let Err(e) = save_scene(&sandbox_window.scene_path, sandbox_window) {
+                    eprintln!("Error saving scene: {}", e);
+                }
+                ui.close_menu();
+            }
+            if ui.button("Load Scene").clicked() {
+                let path = sandbox_window.scene_path.clone();
+                if let Err(e) = load_scene(&path, sandbox_window) {
+                    eprintln!("Error loading scene: {}", e);
+                }
+                ui.close_menu();
+            }
         });
     }
 